@@ -1,173 +1,932 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::process::ExitCode;
+use std::rc::Rc;
+
+//Where some source text was found: 1-based line and column of its start, plus its byte range
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Span {
+    line: usize,
+    column: usize,
+    start: usize,
+    end: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+//Symbols are interned, so comparing or hashing one is comparing or hashing a small id
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Symbol(u32);
+
+struct SymbolTable {
+    ids: HashMap<Rc<str>, Symbol>,
+    names: Vec<Rc<str>>,
+}
+
+//Interned first, so their ids are the constants below and the evaluator compares ids rather than names
+const KEYWORDS: [&str; 14] = ["quote", "cond", "define", "lambda", "if", "and", "or", "let", "let*", "letrec", "begin", "not", "else", "."];
+
+impl SymbolTable {
+    fn new() -> SymbolTable {
+        let mut table = SymbolTable { ids: HashMap::new(), names: Vec::new() };
+        for name in KEYWORDS {
+            table.intern(name);
+        }
+        table
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.ids.get(name) {
+            return *symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        let name: Rc<str> = Rc::from(name);
+        self.names.push(name.clone());
+        self.ids.insert(name, symbol);
+        symbol
+    }
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<SymbolTable> = RefCell::new(SymbolTable::new());
+}
+
+impl Symbol {
+    const QUOTE: Symbol = Symbol(0);
+    const COND: Symbol = Symbol(1);
+    const DEFINE: Symbol = Symbol(2);
+    const LAMBDA: Symbol = Symbol(3);
+    const IF: Symbol = Symbol(4);
+    const AND: Symbol = Symbol(5);
+    const OR: Symbol = Symbol(6);
+    const LET: Symbol = Symbol(7);
+    const LET_STAR: Symbol = Symbol(8);
+    const LETREC: Symbol = Symbol(9);
+    const BEGIN: Symbol = Symbol(10);
+    const NOT: Symbol = Symbol(11);
+    const ELSE: Symbol = Symbol(12);
+    const DOT: Symbol = Symbol(13);
+
+    //The same name always gives the same Symbol
+    fn new(name: &str) -> Symbol {
+        SYMBOLS.with(|symbols| symbols.borrow_mut().intern(name))
+    }
+
+    fn name(self) -> Rc<str> {
+        SYMBOLS.with(|symbols| symbols.borrow().names[self.0 as usize].clone())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({:?})", self.name())
+    }
+}
+
+#[test]
+fn test_symbol() {
+    assert!(Symbol::new("atom") == Symbol::new("atom"));
+    assert!(Symbol::new("atom") != Symbol::new("turkey"));
+    assert_eq!(&*Symbol::new("turkey").name(), "turkey");
+    assert_eq!(format!("{:?}", Symbol::new("or")), "Symbol(\"or\")");
+    let keywords = [Symbol::QUOTE, Symbol::COND, Symbol::DEFINE, Symbol::LAMBDA, Symbol::IF, Symbol::AND, Symbol::OR,
+        Symbol::LET, Symbol::LET_STAR, Symbol::LETREC, Symbol::BEGIN, Symbol::NOT, Symbol::ELSE, Symbol::DOT];
+    for (keyword, name) in keywords.iter().zip(KEYWORDS) {
+        assert_eq!(*keyword, Symbol::new(name));
+    }
+}
+
+//Anything that isn't a list
+#[derive(Debug, Clone, PartialEq)]
+enum Atom {
+    Symbol(Symbol),
+    Number(i64),
+    Bool(bool),
+    Str(String),
+    Char(char),
+}
+
+//As `write` shows it; `{:#}` shows it as `display` does, with strings and characters as plain text
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Atom::Symbol(s) => write!(f, "{}", s),
+            Atom::Number(n) => write!(f, "{}", n),
+            Atom::Bool(true) => write!(f, "#t"),
+            Atom::Bool(false) => write!(f, "#f"),
+            Atom::Str(s) if f.alternate() => write!(f, "{}", s),
+            Atom::Str(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        c if c.is_control() => write!(f, "\\x{:x};", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            },
+            Atom::Char(c) if f.alternate() => write!(f, "{}", c),
+            Atom::Char(' ') => write!(f, "#\\space"),
+            Atom::Char('\n') => write!(f, "#\\newline"),
+            Atom::Char('\t') => write!(f, "#\\tab"),
+            Atom::Char(c) => write!(f, "#\\{}", c),
+        }
+    }
+}
 
 #[derive(Debug)]
 #[derive(PartialEq)]
-enum Token {
+enum TokenKind {
     OpenBracket,
     CloseBracket,
-    Atom(String),
+    Quote,
+    Atom(Atom),
 }
 
-fn to_tokens(text: &str) -> Vec<Token> {
+#[derive(Debug)]
+#[derive(PartialEq)]
+struct Token {
+    kind: TokenKind,
+    span: Span,
+}
 
-    let mut tokens = Vec::<Token>::new();
-    let mut atom = String::new();
+//Not part of the program, but kept for tools such as the formatter
+#[derive(Debug, PartialEq)]
+struct Comment {
+    text: String,
+    span: Span,
+}
+
+//The token as it would be written
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            TokenKind::OpenBracket => write!(f, "("),
+            TokenKind::CloseBracket => write!(f, ")"),
+            TokenKind::Quote => write!(f, "'"),
+            TokenKind::Atom(atom) => write!(f, "{}", atom),
+        }
+    }
+}
+
+//Literal syntax for numbers and booleans, otherwise a symbol
+fn to_atom(text: &str, span: Span) -> Result<Atom, SchemeError> {
+    let digits = text.strip_prefix(|c| c == '+' || c == '-').unwrap_or(text);
+    if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        return text.parse().map(Atom::Number).map_err(|_| SchemeError::BadLiteral(text.to_string(), span));
+    }
+    match text {
+        "#t" | "#true" => Ok(Atom::Bool(true)),
+        "#f" | "#false" => Ok(Atom::Bool(false)),
+        _ if text.starts_with('#') => Err(SchemeError::BadLiteral(text.to_string(), span)),
+        _ => Ok(Atom::Symbol(Symbol::new(text))),
+    }
+}
+
+//Where the datum starting at `tokens[k]` ends, for `#;`
+fn datum_end(tokens: &[Token], k: usize, span: Span) -> Result<usize, SchemeError> {
+    match tokens.get(k).map(|token| &token.kind) {
+        Some(TokenKind::Atom(_)) => Ok(k + 1),
+        Some(TokenKind::Quote) => datum_end(tokens, k + 1, span),
+        Some(TokenKind::OpenBracket) => {
+            let mut depth = 0;
+            for (j, token) in tokens.iter().enumerate().skip(k) {
+                match token.kind {
+                    TokenKind::OpenBracket => depth += 1,
+                    TokenKind::CloseBracket if depth == 1 => return Ok(j + 1),
+                    TokenKind::CloseBracket => depth -= 1,
+                    _ => (),
+                }
+            }
+            Err(SchemeError::UnclosedList(tokens[k].span))
+        },
+        Some(TokenKind::CloseBracket) | None => Err(SchemeError::NothingToComment(span)),
+    }
+}
+
+fn to_tokens(text: &str) -> Result<Vec<Token>, SchemeError> {
+    to_tokens_and_comments(text).map(|(tokens, _)| tokens)
+}
 
-    for c in text.chars() {
-        if !atom.is_empty()
-          && ( c == '(' 
-            || c == ')'
-            || !c.is_ascii_graphic()) {
-            tokens.push(Token::Atom(atom.clone()));
-            atom.clear();
+fn to_tokens_and_comments(text: &str) -> Result<(Vec<Token>, Vec<Comment>), SchemeError> {
+
+    //Every char, with where it is
+    let mut chars = Vec::<(char, Span)>::new();
+    let mut line = 1;
+    let mut column = 1;
+    for (i, c) in text.char_indices() {
+        chars.push((c, Span { line, column, start: i, end: i + c.len_utf8() }));
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
         }
+    }
 
+    let is_delimiter = |c: char| c == '(' || c == ')' || c == '"' || c == '\'' || c == ';' || c.is_whitespace() || c.is_control();
+
+    let mut tokens = Vec::<Token>::new();
+    let mut comments = Vec::<Comment>::new();
+    let mut datum_comments = Vec::<(usize, Span)>::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (c, span) = chars[i];
         if c == '(' {
-            tokens.push(Token::OpenBracket);
+            tokens.push(Token { kind: TokenKind::OpenBracket, span });
+            i += 1;
         }
         else if c == ')' {
-            tokens.push(Token::CloseBracket);
+            tokens.push(Token { kind: TokenKind::CloseBracket, span });
+            i += 1;
+        }
+        else if c == '\'' {
+            tokens.push(Token { kind: TokenKind::Quote, span });
+            i += 1;
+        }
+        else if c == '"' {
+            //String up to the closing quote, with R7RS escapes
+            let mut string = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some(('"', end)) => {
+                        tokens.push(Token { kind: TokenKind::Atom(Atom::Str(string)), span: Span { end: end.end, ..span } });
+                        i += 1;
+                        break;
+                    },
+                    Some(('\\', escape)) => {
+                        i += 1;
+                        match chars.get(i).map(|(c, _)| *c) {
+                            Some(c @ ('"' | '\\' | '|')) => string.push(c),
+                            Some('n') => string.push('\n'),
+                            Some('t') => string.push('\t'),
+                            Some('r') => string.push('\r'),
+                            Some('a') => string.push('\u{7}'),
+                            Some('b') => string.push('\u{8}'),
+                            Some('x') => {
+                                //Hex scalar value, ended by `;`
+                                let digits: String = chars[i + 1..].iter().map(|(c, _)| *c).take_while(char::is_ascii_hexdigit).collect();
+                                i += digits.len() + 1;
+                                match (chars.get(i), u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32)) {
+                                    (Some((';', _)), Some(c)) => string.push(c),
+                                    _ => return Err(SchemeError::BadEscape(*escape)),
+                                }
+                            },
+                            Some(' ' | '\t' | '\n') => {
+                                //Line continuation: the line break goes, along with the spaces around it
+                                while matches!(chars.get(i), Some((' ' | '\t', _))) {
+                                    i += 1;
+                                }
+                                if !matches!(chars.get(i), Some(('\n', _))) {
+                                    return Err(SchemeError::BadEscape(*escape));
+                                }
+                                while matches!(chars.get(i + 1), Some((' ' | '\t', _))) {
+                                    i += 1;
+                                }
+                            },
+                            Some(_) => return Err(SchemeError::BadEscape(*escape)),
+                            None => return Err(SchemeError::UnterminatedString(span)),
+                        }
+                        i += 1;
+                    },
+                    Some((c, _)) => {
+                        string.push(*c);
+                        i += 1;
+                    },
+                    None => return Err(SchemeError::UnterminatedString(span)),
+                }
+            }
+        }
+        else if c == '#' && matches!(chars.get(i + 1), Some(('\\', _))) {
+            //Character: whatever follows the backslash, even a delimiter, or a name such as `space`
+            let mut name = String::new();
+            let mut j = i + 2;
+            if let Some((c, _)) = chars.get(j).filter(|(c, _)| !c.is_whitespace() && !c.is_control()) {
+                name.push(*c);
+                j += 1;
+            }
+            while j < chars.len() && !is_delimiter(chars[j].0) {
+                name.push(chars[j].0);
+                j += 1;
+            }
+            let mut name_chars = name.chars();
+            let c = match (name_chars.next(), name_chars.next(), name.as_str()) {
+                (Some(c), None, _) => c,
+                (_, _, "space") => ' ',
+                (_, _, "newline") => '\n',
+                (_, _, "tab") => '\t',
+                _ => return Err(SchemeError::BadLiteral(format!("#\\{}", name), span)),
+            };
+            tokens.push(Token { kind: TokenKind::Atom(Atom::Char(c)), span: Span { end: chars[j - 1].1.end, ..span } });
+            i = j;
         }
-        else if c.is_ascii_graphic() {
-            atom.push(c);
+        else if c == '#' && matches!(chars.get(i + 1), Some(('|', _))) {
+            //Block comment, which may hold other block comments
+            let mut depth = 0;
+            let mut j = i;
+            while depth > 0 || j == i {
+                match (chars.get(j), chars.get(j + 1)) {
+                    (Some(('#', _)), Some(('|', _))) => depth += 1,
+                    (Some(('|', _)), Some(('#', _))) => depth -= 1,
+                    (Some(_), _) => {
+                        j += 1;
+                        continue;
+                    },
+                    (None, _) => return Err(SchemeError::UnterminatedComment(span)),
+                }
+                j += 2;
+            }
+            let text: String = chars[i..j].iter().map(|(c, _)| c).collect();
+            comments.push(Comment { text, span: Span { end: chars[j - 1].1.end, ..span } });
+            i = j;
+        }
+        else if c == '#' && matches!(chars.get(i + 1), Some((';', _))) {
+            //Datum comment: which tokens it covers is worked out once they're all read
+            datum_comments.push((tokens.len(), span));
+            i += 2;
+        }
+        else if c == ';' {
+            //Comment to the end of the line
+            let mut j = i;
+            while j < chars.len() && chars[j].0 != '\n' {
+                j += 1;
+            }
+            let text: String = chars[i..j].iter().map(|(c, _)| c).collect();
+            comments.push(Comment { text: text.trim_end().to_string(), span: Span { end: chars[j - 1].1.end, ..span } });
+            i = j;
+        }
+        else if c.is_whitespace() {
+            i += 1;
+        }
+        else if c.is_control() {
+            return Err(SchemeError::ControlCharacter(span));
+        }
+        else {
+            //Any other character, in any script, up to a delimiter
+            let mut atom = String::new();
+            let mut j = i;
+            while j < chars.len() && !is_delimiter(chars[j].0) {
+                atom.push(chars[j].0);
+                j += 1;
+            }
+            let span = Span { end: chars[j - 1].1.end, ..span };
+            tokens.push(Token { kind: TokenKind::Atom(to_atom(&atom, span)?), span });
+            i = j;
         }
     }
 
-    if !atom.is_empty() {
-        tokens.push(Token::Atom(atom.clone()));
+    //Innermost first, so `#; #; a b` comments out both `a` and `b`
+    for (k, span) in datum_comments.into_iter().rev() {
+        let end = datum_end(&tokens, k, span)?;
+        let span = Span { end: tokens[end - 1].span.end, ..span };
+        tokens.drain(k..end);
+        comments.retain(|comment| comment.span.start < span.start || comment.span.start >= span.end);
+        comments.push(Comment { text: text[span.start..span.end].to_string(), span });
     }
+    comments.sort_by_key(|comment| comment.span.start);
 
-    tokens
+    Ok((tokens, comments))
 }
 
 #[test]
 fn test_to_tokens() {
     {
-        let tokens = to_tokens("");
+        let tokens = to_tokens("").unwrap();
         assert_eq!(tokens.len(), 0);
     }
 
     {
-        let tokens = to_tokens("a");
+        let tokens = to_tokens("a").unwrap();
         assert_eq!(tokens.len(), 1);
 
-        assert!(match &tokens[0] {
-            Token::Atom(text) => text == "a",
+        assert!(match &tokens[0].kind {
+            TokenKind::Atom(Atom::Symbol(symbol)) => *symbol == Symbol::new("a"),
             _ => false
           });
 
-        assert_eq!(tokens, vec!(Token::Atom("a".to_string())));
+        assert_eq!(tokens, vec!(Token { kind: TokenKind::Atom(Atom::Symbol(Symbol::new("a"))), span: Span { line: 1, column: 1, start: 0, end: 1 } }));
+    }
+
+    {
+        let tokens = to_tokens("(car\n  (abc))").unwrap();
+        let kinds: Vec<&TokenKind> = tokens.iter().map(|token| &token.kind).collect();
+        assert_eq!(kinds, vec!(
+            &TokenKind::OpenBracket,
+            &TokenKind::Atom(Atom::Symbol(Symbol::new("car"))),
+            &TokenKind::OpenBracket,
+            &TokenKind::Atom(Atom::Symbol(Symbol::new("abc"))),
+            &TokenKind::CloseBracket,
+            &TokenKind::CloseBracket));
+
+        assert_eq!(tokens[1].span, Span { line: 1, column: 2, start: 1, end: 4 });
+        assert_eq!(tokens[3].span, Span { line: 2, column: 4, start: 8, end: 11 });
+        assert_eq!(tokens[5].span, Span { line: 2, column: 8, start: 12, end: 13 });
+    }
+
+    {
+        let tokens = to_tokens("(1492 -7 + #t #f \"(a b)\" #\\a #\\) #\\space 7up)").unwrap();
+        let kinds: Vec<&TokenKind> = tokens.iter().map(|token| &token.kind).collect();
+        assert_eq!(kinds, vec!(
+            &TokenKind::OpenBracket,
+            &TokenKind::Atom(Atom::Number(1492)),
+            &TokenKind::Atom(Atom::Number(-7)),
+            &TokenKind::Atom(Atom::Symbol(Symbol::new("+"))),
+            &TokenKind::Atom(Atom::Bool(true)),
+            &TokenKind::Atom(Atom::Bool(false)),
+            &TokenKind::Atom(Atom::Str("(a b)".to_string())),
+            &TokenKind::Atom(Atom::Char('a')),
+            &TokenKind::Atom(Atom::Char(')')),
+            &TokenKind::Atom(Atom::Char(' ')),
+            &TokenKind::Atom(Atom::Symbol(Symbol::new("7up"))),
+            &TokenKind::CloseBracket));
+
+        assert_eq!(tokens[6].span, Span { line: 1, column: 18, start: 17, end: 24 });
+        assert_eq!(tokens[9].span, Span { line: 1, column: 34, start: 33, end: 40 });
+    }
+
+    {
+        let tokens = to_tokens("('a \"b c\" #\\space 7)").unwrap();
+        let texts: Vec<String> = tokens.iter().map(|token| token.to_string()).collect();
+        assert_eq!(texts, vec!("(", "'", "a", "\"b c\"", "#\\space", "7", ")"));
+    }
+
+    {
+        assert_eq!(to_tokens("(a \"b").err(), Some(SchemeError::UnterminatedString(Span { line: 1, column: 4, start: 3, end: 4 })));
+        assert_eq!(to_tokens("#z").err(), Some(SchemeError::BadLiteral("#z".to_string(), Span { line: 1, column: 1, start: 0, end: 2 })));
     }
+
+    {
+        //Atoms in any script, separated by any whitespace, and columns counted in characters
+        let tokens = to_tokens("(↑ λ\u{a0}café\u{2003}#\\λ)").unwrap();
+        let texts: Vec<String> = tokens.iter().map(|token| token.to_string()).collect();
+        assert_eq!(texts, vec!("(", "↑", "λ", "café", "#\\λ", ")"));
+        assert_eq!(tokens[3].span, Span { line: 1, column: 6, start: 9, end: 14 });
+
+        assert_eq!(to_tokens("(a\u{7}b)").err(), Some(SchemeError::ControlCharacter(Span { line: 1, column: 3, start: 2, end: 3 })));
+        assert!(to_tokens("\"a\u{7}b\" ; \u{7}").is_ok(), "control characters are fine in strings and comments");
+    }
+}
+
+#[cfg(test)]
+#[test_case("(a ; b c\n d)", "( a d )", &["; b c"]; "line comment")]
+#[test_case("#| a #| (b |# c |# d", "d", &["#| a #| (b |# c |#"]; "nested block comment")]
+#[test_case("(a #|\n b |#)", "( a )", &["#|\n b |#"]; "block comment over lines")]
+#[test_case("(a #;(b c) d)", "( a d )", &["#;(b c)"]; "datum comment")]
+#[test_case("#;'(a ; b\n c) d ; e", "d", &["#;'(a ; b\n c)", "; e"]; "datum comment holding a line comment")]
+#[test_case("#; #;a b c", "c", &["#; #;a b"]; "datum comment of a datum comment")]
+#[test_case("a#|b|#", "a#|b|#", &[]; "not a comment inside an atom")]
+fn test_comments(s: &str, tokens: &str, comments: &[&str]) {
+    let (found_tokens, found_comments) = to_tokens_and_comments(s).unwrap();
+    assert_eq!(found_tokens.iter().map(|token| token.to_string()).collect::<Vec<String>>().join(" "), tokens);
+    assert_eq!(found_comments.iter().map(|comment| comment.text.as_str()).collect::<Vec<&str>>(), comments);
+}
+
+#[test]
+fn test_comment_errors() {
+    assert_eq!(to_tokens("a #| b #| c |#").err(), Some(SchemeError::UnterminatedComment(Span { line: 1, column: 3, start: 2, end: 3 })));
+    assert_eq!(to_tokens("(a #;)").err(), Some(SchemeError::NothingToComment(Span { line: 1, column: 4, start: 3, end: 4 })));
+    assert_eq!(to_tokens("#;(a").err(), Some(SchemeError::UnclosedList(Span { line: 1, column: 3, start: 2, end: 3 })));
 }
 
 #[derive(Clone)]
 enum SExpression {
-    Atom(String),
-    List(Vec<SExpression>)
+    Atom(Atom),
+    Null, //The empty list
+    Pair(Rc<Pair>), //Shared, so car, cdr and cons don't copy
+    Closure(Rc<Closure>),
+    Primitive(Symbol, Primitive), //A built-in function, with its name
 }
 
-fn to_sexpression(tokens: &[Token]) -> Option<SExpression> {
+//A cons cell. A list read from source remembers where it came from in its first Pair.
+struct Pair {
+    car: SExpression,
+    cdr: SExpression,
+    span: Option<Span>,
+}
 
-    fn to_list(begin: std::slice::Iter<Token>) -> Option<(SExpression, std::slice::Iter<Token>)> {
-        let mut list: Vec<SExpression> = Vec::new();
-        let mut current = begin;
-        loop {
-            let (sexp, next) = match current.next() {
-              Some(Token::Atom(s)) => (SExpression::Atom(s.to_string()), current),
-              Some(Token::OpenBracket) => match to_list(current) {
-                  Some(list) => list,
-                  _ => break, //Bad inner list
-              },
-              Some(Token::CloseBracket) => return Some((SExpression::List(list), current)),
-              None => break, //Ran out of tokens before finding matching CloseBracket
-            };
-            list.push(sexp);
-            current = next;
+//Drop a long list along its cdrs in a loop, rather than recursing once per element, stopping at a shared tail
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let mut cdr = std::mem::replace(&mut self.cdr, SExpression::Null);
+        while let SExpression::Pair(pair) = cdr {
+            match Rc::try_unwrap(pair) {
+                Ok(mut pair) => cdr = std::mem::replace(&mut pair.cdr, SExpression::Null),
+                Err(_) => break,
+            }
         }
-        return None;
-      }
-      
-    if tokens.is_empty() {
-        return None
     }
+}
 
-    let mut current = tokens.iter();
-    let (sexp, mut next) = match current.next() {
-        Some(Token::OpenBracket) => match to_list(current) {
-            Some(sexp_next) => sexp_next,
-            _ => return None,
+//The elements of a list, stopping at the first cdr that isn't a Pair
+struct ListIter<'a>(&'a SExpression);
+
+impl<'a> Iterator for ListIter<'a> {
+    type Item = &'a SExpression;
+
+    fn next(&mut self) -> Option<&'a SExpression> {
+        match self.0 {
+            SExpression::Pair(pair) => {
+                self.0 = &pair.cdr;
+                Some(&pair.car)
+            },
+            _ => None,
+        }
+    }
+}
+
+impl SExpression {
+    fn pair(car: SExpression, cdr: SExpression) -> SExpression {
+        SExpression::Pair(Rc::new(Pair { car, cdr, span: None }))
+    }
+
+    //The elements in order, ending in `tail`: the empty list for a proper list, anything else for a dotted one
+    fn list(elements: Vec<SExpression>, tail: SExpression, span: Option<Span>) -> SExpression {
+        let mut list = tail;
+        for (i, car) in elements.into_iter().enumerate().rev() {
+            let span = if i == 0 { span } else { None };
+            list = SExpression::Pair(Rc::new(Pair { car, cdr: list, span }));
+        }
+        list
+    }
+
+    fn iter(&self) -> ListIter<'_> {
+        ListIter(self)
+    }
+
+    //What's left after the elements: the empty list unless dotted
+    fn tail(&self) -> &SExpression {
+        let mut tail = self;
+        while let SExpression::Pair(pair) = tail {
+            tail = &pair.cdr;
+        }
+        tail
+    }
+
+    fn span(&self) -> Option<Span> {
+        match self {
+            SExpression::Pair(pair) => pair.span,
+            _ => None,
+        }
+    }
+}
+
+//Prints the data as it is, without evaluating any of it
+impl fmt::Display for SExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SExpression::Atom(atom) => fmt::Display::fmt(atom, f),
+            SExpression::Null => write!(f, "()"),
+            SExpression::Pair(_) => {
+                write!(f, "(")?;
+                for (i, sexp) in self.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    fmt::Display::fmt(sexp, f)?;
+                }
+                match self.tail() {
+                    SExpression::Null => write!(f, ")"),
+                    tail => {
+                        write!(f, " . ")?;
+                        fmt::Display::fmt(tail, f)?;
+                        write!(f, ")")
+                    },
+                }
+            },
+            SExpression::Closure(_) => write!(f, "#<procedure>"),
+            SExpression::Primitive(name, _) => write!(f, "#<procedure {}>", name),
+        }
+    }
+}
+
+//The internal structure: Pairs, their spans and the Atoms in them
+impl fmt::Debug for SExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SExpression::Atom(atom) => write!(f, "{:?}", atom),
+            SExpression::Null => write!(f, "Null"),
+            SExpression::Pair(pair) => {
+                let mut tuple = f.debug_tuple("Pair");
+                tuple.field(&pair.car).field(&pair.cdr);
+                if let Some(span) = pair.span {
+                    tuple.field(&span);
+                }
+                tuple.finish()
+            },
+            SExpression::Closure(closure) => f.debug_tuple("Closure").field(&closure.params).finish(),
+            SExpression::Primitive(name, _) => f.debug_tuple("Primitive").field(name).finish(),
+        }
+    }
+}
+
+#[test]
+fn test_display_sexpression() {
+    let read = |s: &str| to_sexpression(&to_tokens(s).unwrap()).unwrap();
+
+    assert_eq!(read("()").to_string(), "()");
+    assert_eq!(read("(a  (b\n c) . d)").to_string(), "(a (b c) . d)");
+    assert_eq!(read("(#t 7 \"x\" #\\space)").to_string(), "(#t 7 \"x\" #\\space)");
+    assert_eq!(read("'a").to_string(), "(quote a)");
+    assert_eq!(read("(\"a\\tb\\x7;\" #\\c . \"d\")").to_string(), "(\"a\\tb\\x7;\" #\\c . \"d\")");
+    assert_eq!(format!("{:#}", read("(\"a\\tb\" #\\c . \"d\")")), "(a\tb c . d)", "display shows strings and characters as plain text");
+    assert_eq!(format!("{:?}", read("(a . 1)")),
+        "Pair(Symbol(Symbol(\"a\")), Number(1), Span { line: 1, column: 1, start: 0, end: 7 })");
+    assert_eq!(format!("{:?}", SExpression::list(vec![SExpression::Null], SExpression::Null, None)), "Pair(Null, Null)");
+
+    //Displaying a bound symbol shows the symbol, not its value
+    let env = Environment::new();
+    env.define(Symbol::new("a"), read("(b c)"));
+    assert_eq!(read("a").to_string(), "a");
+}
+
+#[derive(Debug, PartialEq)]
+enum SchemeError {
+    #[cfg(test)]
+    NoSExpression,
+    UnterminatedString(Span),
+    BadEscape(Span),
+    BadLiteral(String, Span),
+    UnexpectedCloseBracket(Span),
+    UnclosedList(Span),
+    BadDot(Span),
+    NothingToQuote(Span),
+    UnterminatedComment(Span),
+    ControlCharacter(Span),
+    NothingToComment(Span),
+    #[cfg(test)]
+    MoreThanOneSExpression(Span),
+    CarOfAtom,
+    CarOfEmptyList,
+    CdrOfAtom,
+    CdrOfEmptyList,
+    NotANumber(String),
+    NotAnInteger(String), //Any number, negative ones included
+    NotANonNumericAtom(String),
+    NotAList(String),
+    NotAString(String),
+    NotASymbol(String),
+    NotAChar(String),
+    OutOfRange(String),
+    SubOneOfZero,
+    NumberTooLarge,
+    MissingArgument(String),
+    WrongArity { expected: usize, found: usize },
+    NoCondClauseMatched,
+    BadParameters,
+    BadBindings(String),
+    BadDefine,
+    DefinedAsItself(String),
+    UnboundVariable(String),
+    NotAProcedure,
+    DottedList,
+    At(Span, Box<SchemeError>),
+}
+
+impl SchemeError {
+    //Locate an error at the innermost source list it came from
+    fn at(self, span: Option<Span>) -> SchemeError {
+        match (self, span) {
+            (SchemeError::At(span, err), _) => SchemeError::At(span, err),
+            (err, Some(span)) => SchemeError::At(span, Box::new(err)),
+            (err, None) => err,
+        }
+    }
+}
+
+impl fmt::Display for SchemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(test)]
+            SchemeError::NoSExpression => write!(f, "no s-expression"),
+            SchemeError::UnterminatedString(span) => write!(f, "unterminated string starting at {}", span),
+            SchemeError::BadEscape(span) => write!(f, "unknown escape in string at {}", span),
+            SchemeError::BadLiteral(text, span) => write!(f, "bad literal `{}` at {}", text, span),
+            SchemeError::UnexpectedCloseBracket(span) => write!(f, "unexpected `)` at {}", span),
+            SchemeError::UnclosedList(span) => write!(f, "unclosed list opened at {}", span),
+            SchemeError::BadDot(span) => write!(f, "`.` must be between the elements and the last s-expression of a list, at {}", span),
+            SchemeError::NothingToQuote(span) => write!(f, "nothing to quote after `'` at {}", span),
+            SchemeError::UnterminatedComment(span) => write!(f, "unterminated comment starting at {}", span),
+            SchemeError::ControlCharacter(span) => write!(f, "unexpected control character at {}", span),
+            SchemeError::NothingToComment(span) => write!(f, "nothing to comment out after `#;` at {}", span),
+            #[cfg(test)]
+            SchemeError::MoreThanOneSExpression(span) => write!(f, "more than one s-expression, the next starting at {}", span),
+            SchemeError::CarOfAtom => write!(f, "`car` of an atom"),
+            SchemeError::CarOfEmptyList => write!(f, "`car` of the empty list"),
+            SchemeError::CdrOfAtom => write!(f, "`cdr` of an atom"),
+            SchemeError::CdrOfEmptyList => write!(f, "`cdr` of the empty list"),
+            SchemeError::NotANumber(keyword) => write!(f, "`{}` expects a non-negative number", keyword),
+            SchemeError::NotAnInteger(keyword) => write!(f, "`{}` expects a number", keyword),
+            SchemeError::NotANonNumericAtom(keyword) => write!(f, "`{}` expects atoms that aren't numbers", keyword),
+            SchemeError::NotAList(keyword) => write!(f, "`{}` expects lists", keyword),
+            SchemeError::NotAString(keyword) => write!(f, "`{}` expects a string", keyword),
+            SchemeError::NotASymbol(keyword) => write!(f, "`{}` expects a symbol", keyword),
+            SchemeError::NotAChar(keyword) => write!(f, "`{}` expects characters", keyword),
+            SchemeError::OutOfRange(keyword) => write!(f, "`{}` index out of range", keyword),
+            SchemeError::SubOneOfZero => write!(f, "`sub1` of zero"),
+            SchemeError::NumberTooLarge => write!(f, "number too large"),
+            SchemeError::MissingArgument(keyword) => write!(f, "`{}` is missing an argument", keyword),
+            SchemeError::WrongArity { expected, found } => write!(f, "expected {} arguments but found {}", expected, found),
+            SchemeError::NoCondClauseMatched => write!(f, "no `cond` clause matched"),
+            SchemeError::BadParameters => write!(f, "`lambda` parameters must be a list of atoms"),
+            SchemeError::BadBindings(keyword) => write!(f, "`{}` bindings must be a list of (name value) lists", keyword),
+            SchemeError::BadDefine => write!(f, "`define` expects an atom to name"),
+            SchemeError::DefinedAsItself(name) => write!(f, "`{}` is defined as itself", name),
+            SchemeError::UnboundVariable(name) => write!(f, "`{}` is unbound", name),
+            SchemeError::NotAProcedure => write!(f, "the head of the list is not a procedure"),
+            SchemeError::DottedList => write!(f, "a dotted list can't be evaluated"),
+            SchemeError::At(span, err) => write!(f, "{} at {}", err, span),
+        }
+    }
+}
+
+#[test]
+fn test_scheme_error() {
+    //Only strict mode can tell an unbound name or a head that isn't a procedure from data
+    let env = Environment::with_mode(Mode::Strict);
+    let eval = |s: &str| to_sexpression(&to_tokens(s).unwrap()).unwrap().eval(&env).map_err(|err| err.to_string());
+    assert_eq!(eval("(car x)").err().as_deref(), Some("`x` is unbound at 1:1"));
+    assert_eq!(eval("('a 'b)").err().as_deref(), Some("the head of the list is not a procedure at 1:1"));
+    assert_eq!(eval("(car '())").err().as_deref(), Some("`car` of the empty list at 1:1"));
+    assert_eq!(eval("((lambda (x) x))").err().as_deref(), Some("expected 1 arguments but found 0 at 1:1"));
+}
+
+fn to_list(open: Span, begin: std::slice::Iter<Token>) -> Result<(SExpression, std::slice::Iter<Token>), SchemeError> {
+    let mut list: Vec<SExpression> = Vec::new();
+    let mut current = begin;
+    loop {
+        let (sexp, next) = match current.next() {
+          Some(Token { kind: TokenKind::CloseBracket, span }) => {
+              let span = Span { end: span.end, ..open };
+              return Ok((SExpression::list(list, SExpression::Null, Some(span)), current))
+          },
+          //`(a b . c)` has exactly one s-expression between the dot and the close bracket
+          Some(Token { kind: TokenKind::Atom(Atom::Symbol(dot)), span: dot_span }) if *dot == Symbol::DOT => {
+              let (tail, mut next) = match current.next() {
+                  Some(token) if !list.is_empty() && token.kind != TokenKind::CloseBracket => to_datum(token, current)?,
+                  Some(_) => return Err(SchemeError::BadDot(*dot_span)),
+                  None => return Err(SchemeError::UnclosedList(open)),
+              };
+              return match next.next() {
+                  Some(Token { kind: TokenKind::CloseBracket, span }) => {
+                      let span = Span { end: span.end, ..open };
+                      Ok((SExpression::list(list, tail, Some(span)), next))
+                  },
+                  Some(_) => Err(SchemeError::BadDot(*dot_span)),
+                  None => Err(SchemeError::UnclosedList(open)),
+              };
+          },
+          Some(token) => to_datum(token, current)?,
+          None => return Err(SchemeError::UnclosedList(open)), //Ran out of tokens before finding matching CloseBracket
+        };
+        list.push(sexp);
+        current = next;
+    }
+}
+
+//The s-expression starting at `token`, other than a close bracket
+fn to_datum<'a>(token: &Token, current: std::slice::Iter<'a, Token>) -> Result<(SExpression, std::slice::Iter<'a, Token>), SchemeError> {
+    match &token.kind {
+        TokenKind::Atom(atom) => Ok((SExpression::Atom(atom.clone()), current)),
+        TokenKind::OpenBracket => to_list(token.span, current),
+        TokenKind::CloseBracket => Err(SchemeError::UnexpectedCloseBracket(token.span)),
+        //`'x` is read as `(quote x)`
+        TokenKind::Quote => {
+            let mut current = current;
+            let (datum, next) = match current.next() {
+                Some(Token { kind: TokenKind::CloseBracket, .. }) | None => return Err(SchemeError::NothingToQuote(token.span)),
+                Some(next) => (next, current),
+            };
+            let (sexp, next) = to_datum(datum, next)?;
+            let end = sexp.span().map_or(datum.span.end, |span| span.end);
+            let quote = SExpression::Atom(Atom::Symbol(Symbol::QUOTE));
+            Ok((SExpression::list(vec![quote, sexp], SExpression::Null, Some(Span { end, ..token.span })), next))
         },
-        Some(Token::CloseBracket) => return None,
-        Some(Token::Atom(s)) => (SExpression::Atom(s.to_string()), current),
-        None => return None,
+    }
+}
+
+//The next whole s-expression, if there are any tokens left
+fn next_sexpression(begin: std::slice::Iter<Token>) -> Result<Option<(SExpression, std::slice::Iter<Token>)>, SchemeError> {
+    let mut current = begin;
+    match current.next() {
+        Some(token) => Ok(Some(to_datum(token, current)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+fn to_sexpression(tokens: &[Token]) -> Result<SExpression, SchemeError> {
+    let (sexp, mut next) = match next_sexpression(tokens.iter())? {
+        Some(sexp_next) => sexp_next,
+        None => return Err(SchemeError::NoSExpression),
     };
     match next.next() {
-        Some(_) => return None, //More than one sexpression when either list or atom expected
-        _ => Some(sexp),
+        Some(Token { kind: TokenKind::CloseBracket, span }) => Err(SchemeError::UnexpectedCloseBracket(*span)),
+        Some(Token { span, .. }) => Err(SchemeError::MoreThanOneSExpression(*span)), //Either list or atom expected
+        _ => Ok(sexp),
     }
 }
 
 #[test]
+#[allow(unused_parens, clippy::match_like_matches_macro, clippy::assertions_on_constants)]
 fn test_to_sexpression() {
     {
-        let tokens = to_tokens("");
+        let tokens = to_tokens("").unwrap();
         let sexp = to_sexpression(&tokens);
         assert!(match sexp {
-            None => true,
+            Err(SchemeError::NoSExpression) => true,
             _ => false
           });
     }
     {
-        let tokens = to_tokens("()");
+        let tokens = to_tokens("()").unwrap();
         let sexp = to_sexpression(&tokens);
         assert!(match sexp {
-            Some(SExpression::List(list)) => (list.len() == 0),
+            Ok(SExpression::Null) => true,
             _ => false
           });
     }
     {
-        let tokens = to_tokens("a");
+        let tokens = to_tokens("a").unwrap();
         let sexp = to_sexpression(&tokens);
         assert!(match sexp {
-            Some(SExpression::Atom(s)) => s == "a",
+            Ok(SExpression::Atom(Atom::Symbol(s))) => s == Symbol::new("a"),
             _ => false
           });
     }
     {
-        let tokens = to_tokens("(atom turkey) or");
+        let tokens = to_tokens("(atom turkey) or").unwrap();
         let sexp = to_sexpression(&tokens);
-        assert!(match sexp {
-            None => true,
-            _ => false
-          });
+        assert_eq!(sexp.err(), Some(SchemeError::MoreThanOneSExpression(Span { line: 1, column: 15, start: 14, end: 16 })));
+    }
+    {
+        let tokens = to_tokens("((atom turkey) or").unwrap();
+        let sexp = to_sexpression(&tokens);
+        assert_eq!(sexp.err(), Some(SchemeError::UnclosedList(Span { line: 1, column: 1, start: 0, end: 1 })));
+    }
+    {
+        let tokens = to_tokens("(atom turkey))").unwrap();
+        let sexp = to_sexpression(&tokens);
+        assert_eq!(sexp.err(), Some(SchemeError::UnexpectedCloseBracket(Span { line: 1, column: 14, start: 13, end: 14 })));
+    }
+    {
+        let tokens = to_tokens("(atom\n  (turkey))").unwrap();
+        let sexp = to_sexpression(&tokens);
+        match sexp {
+            Ok(list) => {
+                assert_eq!(list.span(), Some(Span { line: 1, column: 1, start: 0, end: 17 }));
+                assert_eq!(list.iter().nth(1).and_then(SExpression::span), Some(Span { line: 2, column: 3, start: 8, end: 16 }));
+            },
+            _ => assert!(false),
+        }
     }
     {
-        let tokens = to_tokens("((atom turkey third) or)");
+        let tokens = to_tokens("((atom turkey third) or)").unwrap();
         let sexp = to_sexpression(&tokens);
 
         match sexp {
-            Some(SExpression::List(list)) => {
+            Ok(list) => {
+                let list: Vec<&SExpression> = list.iter().collect();
                 assert_eq!(list.len(), 2);
-                match &list[0] {
-                    SExpression::List(list2) => {
+                match list[0] {
+                    list2 @ SExpression::Pair(_) => {
+                        let list2: Vec<&SExpression> = list2.iter().collect();
                         assert_eq!(list2.len(), 3);
                         assert!(match &list2[0] {
-                            SExpression::Atom(s) => (s == "atom"),
+                            SExpression::Atom(Atom::Symbol(s)) => (*s == Symbol::new("atom")),
                             _ => false,
                         });
                         assert!(match &list2[1] {
-                            SExpression::Atom(s) => (s == "turkey"),
+                            SExpression::Atom(Atom::Symbol(s)) => (*s == Symbol::new("turkey")),
                             _ => false,
                         });
                         assert!(match &list2[2] {
-                            SExpression::Atom(s) => (s == "third"),
+                            SExpression::Atom(Atom::Symbol(s)) => (*s == Symbol::new("third")),
                             _ => false,
                         });
                     },
                     _ => assert!(false),
                 }
-                assert!(match &list[1] {
-                    SExpression::Atom(s) => (s == "or"),
+                assert!(match list[1] {
+                    SExpression::Atom(Atom::Symbol(s)) => (*s == Symbol::new("or")),
                     _ => false,
                 });
             },
@@ -175,46 +934,85 @@ fn test_to_sexpression() {
           }
     }
     {
-        let tokens = to_tokens("(how are you doing so far)");
+        let tokens = to_tokens("(how are you doing so far)").unwrap();
         let sexp = to_sexpression(&tokens);
 
         assert!(match sexp {
-            Some(SExpression::List(list)) => (list.len() == 6),
+            Ok(list) => (list.iter().count() == 6),
             _ => false,
         });
     }
     {
-        let tokens = to_tokens("(((how) are)((you)(doing so))far)");
+        let tokens = to_tokens("(((how) are)((you)(doing so))far)").unwrap();
         let sexp = to_sexpression(&tokens);
 
         assert!(match sexp {
-            Some(SExpression::List(_)) => true,
+            Ok(SExpression::Pair(_)) => true,
             _ => false,
         });
 
-        assert!(if let Some(SExpression::List(_)) = sexp {
+        assert!(if let Ok(SExpression::Pair(_)) = sexp {
                 true
             } else {
                 false
         });
 
         assert!(match sexp {
-            Some(SExpression::List(list)) => (list.len() == 3),
+            Ok(list) => (list.iter().count() == 3),
             _ => false,
         });
     }
 }
 
+//All the top-level s-expressions, in order, e.g. for a whole file
+fn to_sexpressions(tokens: &[Token]) -> Result<Vec<SExpression>, SchemeError> {
+    let mut sexps = Vec::new();
+    let mut current = tokens.iter();
+    while let Some((sexp, next)) = next_sexpression(current)? {
+        sexps.push(sexp);
+        current = next;
+    }
+    Ok(sexps)
+}
+
+#[test]
+fn test_to_sexpressions() {
+    {
+        let tokens = to_tokens("").unwrap();
+        assert!(matches!(to_sexpressions(&tokens), Ok(sexps) if sexps.is_empty()));
+    }
+    {
+        let tokens = to_tokens("(define a (b c))\n(car a) d").unwrap();
+        let sexps = to_sexpressions(&tokens).unwrap();
+        assert_eq!(sexps.len(), 3);
+        assert_eq!(sexps[0].iter().count(), 3);
+        assert!(matches!(sexps[1].span(), Some(Span { line: 2, column: 1, .. })));
+        assert!(matches!(&sexps[2], SExpression::Atom(Atom::Symbol(s)) if *s == Symbol::new("d")));
+    }
+    {
+        let tokens = to_tokens("(a) (b").unwrap();
+        assert_eq!(to_sexpressions(&tokens).err(), Some(SchemeError::UnclosedList(Span { line: 1, column: 5, start: 4, end: 5 })));
+    }
+    {
+        let tokens = to_tokens("(a) b)").unwrap();
+        assert_eq!(to_sexpressions(&tokens).err(), Some(SchemeError::UnexpectedCloseBracket(Span { line: 1, column: 6, start: 5, end: 6 })));
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::ptr_arg, clippy::match_like_matches_macro)]
 fn is_atom(tokens: &Vec<Token>) -> bool {
     tokens.len() == 1 &&
-    match &tokens[0] {
-        Token::Atom(_) => true,
+    match &tokens[0].kind {
+        TokenKind::Atom(_) => true,
         _ => false
     }
 }
 
+#[cfg(test)]
 use test_case::test_case;
 
+#[cfg(test)]
 #[test_case("atom", true; "is_atom: simple word")]
 #[test_case("turkey", true; "is_atom: simple word 2")]
 #[test_case("1492", true; "is_atom: number")]
@@ -229,30 +1027,31 @@ use test_case::test_case;
 #[test_case("(abc$", false; "is_atom: left bracket and atom")]
 #[test_case("(abc$)", false; "is_atom: bracketed atom")]
 fn test_is_atom(s: &str, expected: bool) {
-    let tokens = to_tokens(s);
+    let tokens = to_tokens(s).unwrap();
     assert_eq!(is_atom(&tokens), expected);
 }
 
+#[cfg(test)]
 fn is_list(tokens: &Vec<Token>) -> bool {
 
     let mut depth = 0;
     let mut max_depth = 0;
     
     for token in tokens {
-        match token {
-            Token::OpenBracket => {
+        match token.kind {
+            TokenKind::OpenBracket => {
                 depth += 1;
                 if depth > max_depth {
                     max_depth = depth;
                 }
             },
-            Token::CloseBracket => {
+            TokenKind::CloseBracket => {
                 depth -= 1;
                 if depth < 0 {
                     return false;
                 }
             },
-            Token::Atom(_) => {
+            TokenKind::Atom(_) | TokenKind::Quote => {
                 //Check that atom isn't found outside outermost list
                 if depth <= 0 {
                     return false;
@@ -264,6 +1063,7 @@ fn is_list(tokens: &Vec<Token>) -> bool {
     depth == 0 && max_depth > 0
 }
 
+#[cfg(test)]
 #[test_case("atom", false; "is_list: atom")]
 #[test_case("(atom)", true; "is_list: one atom list")]
 #[test_case("()", true; "is_list: empty list")]
@@ -274,214 +1074,712 @@ fn is_list(tokens: &Vec<Token>) -> bool {
 #[test_case("(atom turkey) or", false; "is_list: list and atom")]
 #[test_case("((atom turkey) or)", true; "is_list: list of list and atom")]
 fn test_is_list(s: &str, expected: bool) {
-    assert_eq!(is_list(&to_tokens(s)), expected);
+    assert_eq!(is_list(&to_tokens(s).unwrap()), expected);
 }
 
 /// s_expression
+#[cfg(test)]
 fn is_s_exp(tokens: &Vec<Token>) -> bool {
     is_atom(tokens) || is_list(tokens)
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_is_s_exp()
 {
-    assert_eq!(is_s_exp(&to_tokens("")), false);
-    assert_eq!(is_s_exp(&to_tokens(" ")), false);
-    assert_eq!(is_s_exp(&to_tokens("xyz")), true);
-    assert_eq!(is_s_exp(&to_tokens("(x y z)")), true);
-    assert_eq!(is_s_exp(&to_tokens("(x y) z")), false);
-    assert_eq!(is_s_exp(&to_tokens("atom atom")), false);
+    assert_eq!(is_s_exp(&to_tokens("").unwrap()), false);
+    assert_eq!(is_s_exp(&to_tokens(" ").unwrap()), false);
+    assert_eq!(is_s_exp(&to_tokens("xyz").unwrap()), true);
+    assert_eq!(is_s_exp(&to_tokens("(x y z)").unwrap()), true);
+    assert_eq!(is_s_exp(&to_tokens("(x y) z").unwrap()), false);
+    assert_eq!(is_s_exp(&to_tokens("atom atom").unwrap()), false);
 }
 
-type Environment = HashMap<String, SExpression>;
+//Lenient answers the book's questions as written: unbound symbols are themselves and unquoted lists are data.
+//Strict is Scheme: unbound symbols are errors, lists are applications, and data must be quoted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Lenient,
+    Strict,
+}
 
-impl SExpression {
-    fn car(&self) -> Option<SExpression> {
-        match &*self {
-            SExpression::List(list) if !list.is_empty() => Some(list[0].clone()),
-            _ => None,
-        }
-    }
+//A Frame of bindings, chained to the Frame it was created in
+struct Frame {
+    bindings: HashMap<Symbol, SExpression>,
+    parent: Option<Environment>,
+    mode: Mode,
+}
 
-    fn cdr(&self) -> Option<SExpression> {
-        match &*self {
-            SExpression::List(list) if !list.is_empty() => Some(SExpression::List(list[1..].to_vec())),
-            _ => None,
-        }
+//Cloning an Environment shares its Frames rather than copying them
+#[derive(Clone)]
+struct Environment(Rc<RefCell<Frame>>);
+
+impl Environment {
+    //The outermost Frame, where `true` and `false` name the booleans as the book's answers expect
+    #[cfg(test)]
+    fn new() -> Environment {
+        Environment::with_mode(Mode::Lenient)
     }
-    
-    fn cons(&self, list: &SExpression) -> Option<SExpression> {
-        match list {
-            SExpression::List(list) => {
-                let mut list = list.clone();
-                list.insert(0, self.clone());
-                Some(SExpression::List(list))
-            },
-            _ => None,
+
+    fn with_mode(mode: Mode) -> Environment {
+        let env = Environment(Rc::new(RefCell::new(Frame { bindings: HashMap::new(), parent: None, mode })));
+        env.define(Symbol::new("true"), SExpression::Atom(Atom::Bool(true)));
+        env.define(Symbol::new("false"), SExpression::Atom(Atom::Bool(false)));
+        for (name, primitive) in PRIMITIVES {
+            let name = Symbol::new(name);
+            env.define(name, SExpression::Primitive(name, *primitive));
         }
+        env
     }
-    
-    fn is_null(&self) -> SExpression {
-        SExpression::Atom(match &*self {
-            SExpression::List(list) => list.is_empty().to_string(),
-            _ => String::from("false"),
-        })
+
+    //A new innermost Frame, for the parameters of a closure
+    fn extend(&self) -> Environment {
+        Environment(Rc::new(RefCell::new(Frame { bindings: HashMap::new(), parent: Some(self.clone()), mode: self.mode() })))
     }
-    
-    //`quote` returns the following parameter without evaluation
-    fn quote(&self) -> Option<SExpression> {
-        Some(self.clone())
+
+    fn mode(&self) -> Mode {
+        self.0.borrow().mode
     }
 
-    fn is_atom_(&self) -> bool {
-        match &*self {
-            SExpression::Atom(_) => true,
-            _ => false,
+    //Look in the innermost Frame first, then walk outwards
+    fn get(&self, name: Symbol) -> Option<SExpression> {
+        let frame = self.0.borrow();
+        match frame.bindings.get(&name) {
+            Some(sexp) => Some(sexp.clone()),
+            _ => frame.parent.as_ref()?.get(name),
         }
     }
-    
-    fn is_atom(&self) -> SExpression {
-        SExpression::Atom(self.is_atom_().to_string())
-    }
-    
-    fn is_eq(&self, other: &SExpression) -> SExpression {
-        fn is_eq_(lhs: &SExpression, rhs: &SExpression) -> bool {
-            match lhs {
-                SExpression::Atom(lhs) => match rhs {
-                    SExpression::Atom(rhs) => (lhs == rhs),
-                    _ => false,
-                },
-                SExpression::List(lhs) => match rhs {
-                    SExpression::List(rhs) =>
-                         (lhs.len() == rhs.len()) &&
-                         lhs.iter().zip(rhs).any(|(lhs,rhs)|is_eq_(&lhs,&rhs)),
-                    _ => false,
-                },
-            }
-        }
 
-        SExpression::Atom(is_eq_(self, other).to_string())
+    //Always binds in the innermost Frame, shadowing any outer binding
+    fn define(&self, name: Symbol, sexp: SExpression) {
+        self.0.borrow_mut().bindings.insert(name, sexp);
     }
+}
 
-    fn is_lat(&self) -> SExpression {
-        SExpression::Atom((match &*self {
-            SExpression::Atom(_) => false,
-            SExpression::List(list) => list.iter().all(|s|s.is_atom_()),
-        }).to_string())
-    }
+#[test]
+fn test_environment() {
+    let global = Environment::new();
+    global.define(Symbol::new("a"), SExpression::Atom(Atom::Number(1)));
+    global.define(Symbol::new("b"), SExpression::Atom(Atom::Number(2)));
 
-    fn is_true(&self) -> bool {
-        match &*self {
-            SExpression::Atom(s) => (s == "true"),
-            _ => false,
+    let local = global.extend();
+    local.define(Symbol::new("a"), SExpression::Atom(Atom::Number(3)));
+
+    assert!(matches!(local.get(Symbol::new("a")), Some(SExpression::Atom(Atom::Number(3)))));
+    assert!(matches!(local.get(Symbol::new("b")), Some(SExpression::Atom(Atom::Number(2)))));
+    assert!(matches!(global.get(Symbol::new("a")), Some(SExpression::Atom(Atom::Number(1)))));
+    assert!(local.get(Symbol::new("c")).is_none());
+
+    //Definitions made later in an outer Frame are visible from inner Frames
+    global.define(Symbol::new("c"), SExpression::Atom(Atom::Number(4)));
+    assert!(matches!(local.get(Symbol::new("c")), Some(SExpression::Atom(Atom::Number(4)))));
+}
+
+//A `lambda` captures the Environment it was evaluated in
+struct Closure {
+    params: Vec<Symbol>,
+    body: SExpression,
+    env: Environment,
+}
+
+impl Closure {
+    //The last expression of the body is left for the caller to evaluate, as it is in tail position
+    fn apply(&self, args: Vec<SExpression>) -> Result<Tail, SchemeError> {
+        if args.len() != self.params.len() {
+            return Err(SchemeError::WrongArity { expected: self.params.len(), found: args.len() });
+        }
+        let local = self.env.extend();
+        for (param, arg) in self.params.iter().zip(args) {
+            local.define(*param, arg);
+        }
+        self.body.begin(&local)
+    }
+}
+
+//One step of evaluation: either a value, or an expression in tail position still to be evaluated
+enum Tail {
+    Value(SExpression),
+    Eval(SExpression, Environment),
+}
+
+impl SExpression {
+    fn car(&self) -> Result<SExpression, SchemeError> {
+        match self {
+            SExpression::Pair(pair) => Ok(pair.car.clone()),
+            SExpression::Null => Err(SchemeError::CarOfEmptyList),
+            _ => Err(SchemeError::CarOfAtom),
+        }
+    }
+
+    fn cdr(&self) -> Result<SExpression, SchemeError> {
+        match self {
+            SExpression::Pair(pair) => Ok(pair.cdr.clone()),
+            SExpression::Null => Err(SchemeError::CdrOfEmptyList),
+            _ => Err(SchemeError::CdrOfAtom),
         }
     }
     
-    fn cond(&self, conditions: &mut std::slice::Iter<SExpression>, env: &mut Environment) -> Option<SExpression> {
-        let applicable_condition = conditions.find(|&condition| {
-            match condition {
-                SExpression::List(condition) if condition.len() > 1 => match condition[0].eval(env) {
-                    Some(condition) => condition.is_true(),
-                    _ => false,
+    //Consing onto anything but a list makes a dotted pair
+    fn cons(&self, other: &SExpression) -> Result<SExpression, SchemeError> {
+        Ok(SExpression::pair(self.clone(), other.clone()))
+    }
+    
+    fn is_null(&self) -> SExpression {
+        SExpression::Atom(Atom::Bool(matches!(self, SExpression::Null)))
+    }
+
+    fn is_pair(&self) -> SExpression {
+        SExpression::Atom(Atom::Bool(matches!(self, SExpression::Pair(_))))
+    }
+
+    //A proper list: a chain of Pairs ending in the empty list
+    fn is_list_(&self) -> bool {
+        matches!(self.tail(), SExpression::Null)
+    }
+
+    fn is_list(&self) -> SExpression {
+        SExpression::Atom(Atom::Bool(self.is_list_()))
+    }
+    
+    //`quote` returns the following parameter without evaluation
+    fn quote(&self) -> Result<SExpression, SchemeError> {
+        Ok(self.clone())
+    }
+
+    fn is_atom_(&self) -> bool {
+        matches!(self, SExpression::Atom(_))
+    }
+    
+    fn is_atom(&self) -> SExpression {
+        SExpression::Atom(Atom::Bool(self.is_atom_()))
+    }
+
+    fn is_number(&self) -> SExpression {
+        SExpression::Atom(Atom::Bool(matches!(self, SExpression::Atom(Atom::Number(_)))))
+    }
+
+    //The book's numbers are the non-negative integers
+    fn number(&self, keyword: &str) -> Result<i64, SchemeError> {
+        match self {
+            SExpression::Atom(Atom::Number(n)) if *n >= 0 => Ok(*n),
+            _ => Err(SchemeError::NotANumber(keyword.to_string())),
+        }
+    }
+
+    fn add1(&self) -> Result<SExpression, SchemeError> {
+        let n = self.number("add1")?.checked_add(1).ok_or(SchemeError::NumberTooLarge)?;
+        Ok(SExpression::Atom(Atom::Number(n)))
+    }
+
+    fn sub1(&self) -> Result<SExpression, SchemeError> {
+        match self.number("sub1")? {
+            0 => Err(SchemeError::SubOneOfZero),
+            n => Ok(SExpression::Atom(Atom::Number(n - 1))),
+        }
+    }
+
+    fn is_zero(&self) -> Result<SExpression, SchemeError> {
+        Ok(SExpression::Atom(Atom::Bool(self.number("zero?")? == 0)))
+    }
+
+    fn is_boolean(&self) -> SExpression {
+        SExpression::Atom(Atom::Bool(matches!(self, SExpression::Atom(Atom::Bool(_)))))
+    }
+
+    fn is_symbol(&self) -> SExpression {
+        SExpression::Atom(Atom::Bool(matches!(self, SExpression::Atom(Atom::Symbol(_)))))
+    }
+
+    fn string_to_symbol(&self) -> Result<SExpression, SchemeError> {
+        match self {
+            SExpression::Atom(Atom::Str(s)) => Ok(SExpression::Atom(Atom::Symbol(Symbol::new(s)))),
+            _ => Err(SchemeError::NotAString("string->symbol".to_string())),
+        }
+    }
+
+    fn symbol_to_string(&self) -> Result<SExpression, SchemeError> {
+        match self {
+            SExpression::Atom(Atom::Symbol(s)) => Ok(SExpression::Atom(Atom::Str(s.name().to_string()))),
+            _ => Err(SchemeError::NotASymbol("symbol->string".to_string())),
+        }
+    }
+
+    fn string(&self, keyword: &str) -> Result<&str, SchemeError> {
+        match self {
+            SExpression::Atom(Atom::Str(s)) => Ok(s),
+            _ => Err(SchemeError::NotAString(keyword.to_string())),
+        }
+    }
+
+    fn is_string(&self) -> SExpression {
+        SExpression::Atom(Atom::Bool(matches!(self, SExpression::Atom(Atom::Str(_)))))
+    }
+
+    //In characters, not bytes
+    fn string_length(&self) -> Result<SExpression, SchemeError> {
+        Ok(SExpression::Atom(Atom::Number(self.string("string-length")?.chars().count() as i64)))
+    }
+
+    fn string_append(strings: &[SExpression]) -> Result<SExpression, SchemeError> {
+        Ok(SExpression::Atom(Atom::Str(strings.iter().map(|s| s.string("string-append")).collect::<Result<String, SchemeError>>()?)))
+    }
+
+    //The characters from `start` up to, but not including, `end`
+    fn substring(&self, start: &SExpression, end: &SExpression) -> Result<SExpression, SchemeError> {
+        let chars: Vec<char> = self.string("substring")?.chars().collect();
+        let (start, end) = (start.number("substring")? as usize, end.number("substring")? as usize);
+        if start > end || end > chars.len() {
+            return Err(SchemeError::OutOfRange("substring".to_string()));
+        }
+        Ok(SExpression::Atom(Atom::Str(chars[start..end].iter().collect())))
+    }
+
+    fn is_string_eq(&self, other: &SExpression) -> Result<SExpression, SchemeError> {
+        Ok(SExpression::Atom(Atom::Bool(self.string("string=?")? == other.string("string=?")?)))
+    }
+
+    fn string_to_list(&self) -> Result<SExpression, SchemeError> {
+        let chars = self.string("string->list")?.chars().map(|c| SExpression::Atom(Atom::Char(c))).collect();
+        Ok(SExpression::list(chars, SExpression::Null, None))
+    }
+
+    fn list_to_string(&self) -> Result<SExpression, SchemeError> {
+        if !self.is_list_() {
+            return Err(SchemeError::NotAList("list->string".to_string()));
+        }
+        let string = self.iter().map(|sexp| match sexp {
+            SExpression::Atom(Atom::Char(c)) => Ok(*c),
+            _ => Err(SchemeError::NotAChar("list->string".to_string())),
+        }).collect::<Result<String, SchemeError>>()?;
+        Ok(SExpression::Atom(Atom::Str(string)))
+    }
+
+    fn number_to_string(&self) -> Result<SExpression, SchemeError> {
+        match self {
+            SExpression::Atom(Atom::Number(n)) => Ok(SExpression::Atom(Atom::Str(n.to_string()))),
+            _ => Err(SchemeError::NotAnInteger("number->string".to_string())),
+        }
+    }
+    
+    //As in the book, `eq?` only compares atoms that aren't numbers
+    fn is_eq(&self, other: &SExpression) -> Result<SExpression, SchemeError> {
+        match (self, other) {
+            (SExpression::Atom(Atom::Number(_)), _) | (_, SExpression::Atom(Atom::Number(_))) |
+            (SExpression::Null | SExpression::Pair(_), _) | (_, SExpression::Null | SExpression::Pair(_)) |
+            (SExpression::Closure(_) | SExpression::Primitive(..), _) | (_, SExpression::Closure(_) | SExpression::Primitive(..)) =>
+                Err(SchemeError::NotANonNumericAtom("eq?".to_string())),
+            (SExpression::Atom(lhs), SExpression::Atom(rhs)) => Ok(SExpression::Atom(Atom::Bool(lhs == rhs))),
+        }
+    }
+
+    //Atoms, numbers included, are the same if they have the same value; lists if all their elements are
+    fn is_equal_(&self, other: &SExpression) -> bool {
+        let (mut lhs, mut rhs) = (self, other);
+        loop {
+            match (lhs, rhs) {
+                (SExpression::Pair(lhs_pair), SExpression::Pair(rhs_pair)) => {
+                    if !lhs_pair.car.is_equal_(&rhs_pair.car) {
+                        return false;
+                    }
+                    lhs = &lhs_pair.cdr;
+                    rhs = &rhs_pair.cdr;
                 },
-                _ => false,
+                (SExpression::Atom(lhs), SExpression::Atom(rhs)) => return lhs == rhs,
+                (SExpression::Null, SExpression::Null) => return true,
+                (SExpression::Closure(lhs), SExpression::Closure(rhs)) => return Rc::ptr_eq(lhs, rhs),
+                (SExpression::Primitive(lhs, _), SExpression::Primitive(rhs, _)) => return lhs == rhs,
+                _ => return false,
             }
-        });
-        match applicable_condition {
-            Some(SExpression::List(condition)) => condition[1].eval(env),
-            _ => None,
         }
     }
 
-    fn define(&self, other: &SExpression, env: &mut Environment) {
-        match *&self {
-            SExpression::Atom(s) => {env.insert(s.to_string(), other.clone()); ()},
-            _ => (),
+    fn is_equal(&self, other: &SExpression) -> SExpression {
+        SExpression::Atom(Atom::Bool(self.is_equal_(other)))
+    }
+
+    fn is_eqlist(&self, other: &SExpression) -> Result<SExpression, SchemeError> {
+        match (self, other) {
+            (lhs, rhs) if lhs.is_list_() && rhs.is_list_() => Ok(self.is_equal(other)),
+            _ => Err(SchemeError::NotAList("eqlist?".to_string())),
+        }
+    }
+
+    fn is_lat(&self) -> SExpression {
+        SExpression::Atom(Atom::Bool(match self {
+            list if list.is_list_() => list.iter().all(|s|s.is_atom_()),
+            _ => false,
+        }))
+    }
+
+    //Everything but #f counts as true
+    fn is_true(&self) -> bool {
+        !matches!(self, SExpression::Atom(Atom::Bool(false)))
+    }
+    
+    fn cond(&self, conditions: &mut ListIter, env: &Environment) -> Result<Tail, SchemeError> {
+        for condition in conditions {
+            let mut clause = condition.iter();
+            if let Some(test) = clause.next() {
+                //`else` always matches
+                let value = match test {
+                    SExpression::Atom(Atom::Symbol(s)) if *s == Symbol::ELSE => SExpression::Atom(Atom::Bool(true)),
+                    test => test.eval(env)?,
+                };
+                if value.is_true() {
+                    //A clause with just a test gives the test's value
+                    return match clause.0 {
+                        SExpression::Pair(_) => clause.0.begin(env),
+                        _ => Ok(Tail::Value(value)),
+                    };
+                }
+            }
+        }
+        Err(SchemeError::NoCondClauseMatched)
+    }
+
+    //`if` evaluates its test, then only the branch it picks, in tail position. Without an alternative, a false test gives `#f`.
+    fn if_(&self, consequent: &SExpression, alternative: Option<&SExpression>, env: &Environment) -> Result<Tail, SchemeError> {
+        match (self.eval(env)?.is_true(), alternative) {
+            (true, _) => Ok(Tail::Eval(consequent.clone(), env.clone())),
+            (false, Some(alternative)) => Ok(Tail::Eval(alternative.clone(), env.clone())),
+            (false, None) => Ok(Tail::Value(SExpression::Atom(Atom::Bool(false)))),
+        }
+    }
+
+    //`and` stops at the first false value, `or` at the first true one. Either way the last expression is in tail position.
+    fn and(&self, exprs: &mut ListIter, env: &Environment) -> Result<Tail, SchemeError> {
+        let exprs: Vec<&SExpression> = exprs.collect();
+        let Some((last, exprs)) = exprs.split_last() else {
+            return Ok(Tail::Value(SExpression::Atom(Atom::Bool(true))));
+        };
+        for expr in exprs {
+            if !expr.eval(env)?.is_true() {
+                return Ok(Tail::Value(SExpression::Atom(Atom::Bool(false))));
+            }
+        }
+        Ok(Tail::Eval((*last).clone(), env.clone()))
+    }
+
+    fn or(&self, exprs: &mut ListIter, env: &Environment) -> Result<Tail, SchemeError> {
+        let exprs: Vec<&SExpression> = exprs.collect();
+        let Some((last, exprs)) = exprs.split_last() else {
+            return Ok(Tail::Value(SExpression::Atom(Atom::Bool(false))));
+        };
+        for expr in exprs {
+            let value = expr.eval(env)?;
+            if value.is_true() {
+                return Ok(Tail::Value(value));
+            }
+        }
+        Ok(Tail::Eval((*last).clone(), env.clone()))
+    }
+
+    //A body: each expression evaluated in turn, for its effects, then the last left in tail position
+    fn begin(&self, env: &Environment) -> Result<Tail, SchemeError> {
+        let mut exprs = self.iter();
+        let Some(mut expr) = exprs.next() else {
+            return Ok(Tail::Value(SExpression::Null));
+        };
+        for next in exprs {
+            expr.eval(env)?;
+            expr = next;
+        }
+        Ok(Tail::Eval(expr.clone(), env.clone()))
+    }
+
+    //The `(name value)` lists of a `let`
+    fn bindings(&self, keyword: Symbol) -> Result<Vec<(Symbol, &SExpression)>, SchemeError> {
+        if !self.is_list_() {
+            return Err(SchemeError::BadBindings(keyword.to_string()));
+        }
+        self.iter().map(|binding| {
+            let mut parts = binding.iter();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(SExpression::Atom(Atom::Symbol(name))), Some(value), None) if binding.is_list_() => Ok((*name, value)),
+                _ => Err(SchemeError::BadBindings(keyword.to_string())),
+            }
+        }).collect()
+    }
+
+    //`let` evaluates its values outside, then binds them in a new scope for its body.
+    //A named `let` also binds its name, in that scope, to a procedure that runs the body again with new values.
+    fn let_(&self, rest: &mut ListIter, keyword: Symbol, env: &Environment) -> Result<Tail, SchemeError> {
+        match self {
+            SExpression::Atom(Atom::Symbol(name)) => {
+                let bindings = next(rest, keyword)?.bindings(keyword)?;
+                let body = body(rest, keyword)?;
+                let args = bindings.iter().map(|(_, value)| value.eval(env)).collect::<Result<Vec<SExpression>, SchemeError>>()?;
+                let local = env.extend();
+                let params = bindings.iter().map(|(param, _)| *param).collect();
+                let closure = Rc::new(Closure { params, body: body.clone(), env: local.clone() });
+                local.define(*name, SExpression::Closure(closure.clone()));
+                closure.apply(args)
+            },
+            _ => {
+                let bindings = self.bindings(keyword)?;
+                let body = body(rest, keyword)?;
+                let local = env.extend();
+                for (name, value) in bindings {
+                    local.define(name, value.eval(env)?);
+                }
+                body.begin(&local)
+            },
+        }
+    }
+
+    //`let*` evaluates each value in a scope holding the bindings before it
+    fn let_star(&self, body: &SExpression, keyword: Symbol, env: &Environment) -> Result<Tail, SchemeError> {
+        let mut local = env.extend();
+        for (name, value) in self.bindings(keyword)? {
+            let value = value.eval(&local)?;
+            local = local.extend();
+            local.define(name, value);
+        }
+        body.begin(&local)
+    }
+
+    //`letrec` evaluates each value in the new scope itself, so procedures can call each other
+    fn letrec(&self, body: &SExpression, keyword: Symbol, env: &Environment) -> Result<Tail, SchemeError> {
+        let local = env.extend();
+        for (name, value) in self.bindings(keyword)? {
+            let value = value.eval(&local)?;
+            local.define(name, value);
         }
+        body.begin(&local)
     }
 
-    fn eval(&self, env: &mut Environment) -> Option<SExpression> {
-        fn eval_list(list: &Vec<SExpression>, env: &mut Environment) -> Option<SExpression> {
+    //`define` binds the name, unevaluated, to the value of the expression
+    fn define(&self, other: &SExpression, env: &Environment) -> Result<(), SchemeError> {
+        match self {
+            SExpression::Atom(Atom::Symbol(s)) => {
+                let value = other.eval(env)?;
+                //In lenient mode an unbound name evaluates to itself, so `(define a a)`, or `(define b a) (define a b)`,
+                //would bind `a` to `a`. Only a bare name is checked: `(define x 'x)` means it.
+                if env.mode() == Mode::Lenient && matches!((other, &value),
+                    (SExpression::Atom(Atom::Symbol(_)), SExpression::Atom(Atom::Symbol(v))) if v == s) {
+                    return Err(SchemeError::DefinedAsItself(s.to_string()));
+                }
+                env.define(*s, value);
+                Ok(())
+            },
+            _ => Err(SchemeError::BadDefine),
+        }
+    }
+
+    //`lambda` takes a list of parameter names and a body of one or more expressions, without evaluating either
+    fn lambda(&self, body: &SExpression, env: &Environment) -> Result<SExpression, SchemeError> {
+        let params = match self {
+            params if params.is_list_() => params.iter().map(|param| match param {
+                SExpression::Atom(Atom::Symbol(s)) => Ok(*s),
+                _ => Err(SchemeError::BadParameters),
+            }).collect::<Result<Vec<Symbol>, SchemeError>>()?,
+            _ => return Err(SchemeError::BadParameters),
+        };
+        Ok(SExpression::Closure(Rc::new(Closure { params, body: body.clone(), env: env.clone() })))
+    }
+
+    //Tail positions are evaluated in this loop rather than by recursion, so tail calls run in constant stack
+    fn eval(&self, env: &Environment) -> Result<SExpression, SchemeError> {
+        let mut step = self.eval_step(env)?;
+        loop {
+            match step {
+                Tail::Value(sexp) => return Ok(sexp),
+                Tail::Eval(sexp, env) => step = sexp.eval_step(&env).map_err(|err| err.at(self.span()))?,
+            }
+        }
+    }
+
+    fn eval_step(&self, env: &Environment) -> Result<Tail, SchemeError> {
+        //`data` is set for a list inside a list that turned out to be data, in lenient mode
+        fn eval_list(list: &SExpression, env: &Environment, data: bool) -> Result<Tail, SchemeError> {
             let mut new_list : Vec<SExpression> = Vec::new();
             let mut current = list.iter();
             while let Some(sexp) = current.next() {
-              match sexp {
-                  SExpression::Atom(a) if a == "car" => return current.next()?.eval(env)?.car(),
-                  SExpression::Atom(a) if a == "cdr" => return current.next()?.eval(env)?.cdr(),
-                  SExpression::Atom(a) if a == "cons" => return current.next()?.eval(env)?.cons(&current.next()?.eval(env)?),
-                  SExpression::Atom(a) if a == "null?" => return Some(current.next()?.eval(env)?.is_null()),
-                  SExpression::Atom(a) if a == "quote" || a == "'" => return current.next()?.quote(),
-                  SExpression::Atom(a) if a == "atom?" => return Some(current.next()?.eval(env)?.is_atom()),
-                  SExpression::Atom(a) if a == "eq?" => return Some(current.next()?.eval(env)?.is_eq(&current.next()?.eval(env)?)),
-                  SExpression::Atom(a) if a == "lat?" => return Some(current.next()?.eval(env)?.is_lat()),
-                  SExpression::Atom(a) if a == "cond" => return sexp.cond(&mut current, env),
-                  SExpression::Atom(a) if a == "define" => current.next()?.eval(env)?.define(&current.next()?.eval(env)?, env),
-                  _ => match sexp.eval(env) {
-                      Some(sexp) => new_list.push(sexp),
+              if let SExpression::Atom(Atom::Symbol(a)) = sexp {
+                  let a = *a;
+                  let code = !data && new_list.is_empty();
+                  match a {
+                      Symbol::QUOTE => return next(&mut current, a)?.quote().map(Tail::Value),
+                      Symbol::COND => return sexp.cond(&mut current, env),
+                      //Only at the head of a list that is code, as several are everyday words in the book's lists
+                      Symbol::IF if code => return next(&mut current, a)?.if_(next(&mut current, a)?, current.next(), env),
+                      Symbol::AND if code => return sexp.and(&mut current, env),
+                      Symbol::OR if code => return sexp.or(&mut current, env),
+                      Symbol::LET if code => return next(&mut current, a)?.let_(&mut current, a, env),
+                      Symbol::LET_STAR if code => return next(&mut current, a)?.let_star(body(&current, a)?, a, env),
+                      Symbol::LETREC if code => return next(&mut current, a)?.letrec(body(&current, a)?, a, env),
+                      Symbol::BEGIN if code => return body(&current, a)?.begin(env),
+                      Symbol::NOT if code => return Ok(Tail::Value(SExpression::Atom(Atom::Bool(!next(&mut current, a)?.eval(env)?.is_true())))),
+                      Symbol::DEFINE => {
+                          next(&mut current, a)?.define(next(&mut current, a)?, env)?;
+                          continue;
+                      },
+                      Symbol::LAMBDA => return next(&mut current, a)?.lambda(body(&current, a)?, env).map(Tail::Value),
                       _ => (),
+                  }
+              }
+              //Once the head isn't a procedure, the rest of the list is data, and so are the lists in it
+              let value = if data || !new_list.is_empty() { eval_data(sexp, env)? } else { sexp.eval(env)? };
+              match value {
+                  SExpression::Primitive(name, primitive) => return primitive.apply(name, &mut current, env).map(Tail::Value),
+                  //A closure at the head of a list is applied to the rest of the list
+                  SExpression::Closure(closure) if new_list.is_empty() => {
+                      let args = current.map(|arg| arg.eval(env)).collect::<Result<Vec<SExpression>, SchemeError>>()?;
+                      return closure.apply(args);
                   },
+                  _ if env.mode() == Mode::Strict => return Err(SchemeError::NotAProcedure),
+                  sexp => new_list.push(sexp),
               }
             }
-            Some(SExpression::List(new_list))
+            Ok(Tail::Value(SExpression::list(new_list, SExpression::Null, None)))
+        }
+        fn eval_data(sexp: &SExpression, env: &Environment) -> Result<SExpression, SchemeError> {
+            match sexp {
+                SExpression::Pair(pair) if sexp.is_list_() => match eval_list(sexp, env, true).map_err(|err| err.at(pair.span))? {
+                    Tail::Value(value) => Ok(value),
+                    Tail::Eval(tail, env) => tail.eval(&env).map_err(|err| err.at(pair.span)),
+                },
+                _ => sexp.eval(env),
+            }
         }
-        match &*self {
-            SExpression::List(list) => eval_list(&list, env),
-            SExpression::Atom(s) => match env.get(s) {
-                Some(sexp) => sexp.clone().eval(env),
-                _ => Some(self.clone()),
+        match self {
+            SExpression::Pair(pair) if !self.is_list_() => Err(SchemeError::DottedList.at(pair.span)),
+            SExpression::Pair(pair) => eval_list(self, env, false).map_err(|err| err.at(pair.span)),
+            SExpression::Null => Ok(Tail::Value(SExpression::Null)),
+            //The stored value has already been evaluated
+            SExpression::Atom(Atom::Symbol(s)) => match env.get(*s) {
+                Some(sexp) => Ok(Tail::Value(sexp)),
+                None if env.mode() == Mode::Strict => Err(SchemeError::UnboundVariable(s.to_string())),
+                None => Ok(Tail::Value(self.clone())),
+            },
+            SExpression::Atom(_) => Ok(Tail::Value(self.clone())),
+            SExpression::Closure(_) | SExpression::Primitive(..) => Ok(Tail::Value(self.clone())),
+        }
+    }
+}
+
+fn next<'a>(current: &mut ListIter<'a>, keyword: Symbol) -> Result<&'a SExpression, SchemeError> {
+    current.next().ok_or_else(|| SchemeError::MissingArgument(keyword.to_string()))
+}
+
+//The rest of the list, as a body of one or more expressions
+fn body<'a>(current: &ListIter<'a>, keyword: Symbol) -> Result<&'a SExpression, SchemeError> {
+    match current.0 {
+        SExpression::Pair(_) => Ok(current.0),
+        _ => Err(SchemeError::MissingArgument(keyword.to_string())),
+    }
+}
+
+//Built-in functions, applied to their evaluated arguments. Too few or too many arguments is an error.
+#[derive(Clone, Copy)]
+enum Primitive {
+    Unary(fn(&SExpression) -> Result<SExpression, SchemeError>),
+    Binary(fn(&SExpression, &SExpression) -> Result<SExpression, SchemeError>),
+    Ternary(fn(&SExpression, &SExpression, &SExpression) -> Result<SExpression, SchemeError>),
+    Variadic(fn(&[SExpression]) -> Result<SExpression, SchemeError>), //Takes any number of arguments
+}
+
+impl Primitive {
+    fn apply(&self, name: Symbol, current: &mut ListIter, env: &Environment) -> Result<SExpression, SchemeError> {
+        let arity = match self {
+            Primitive::Unary(_) => Some(1),
+            Primitive::Binary(_) => Some(2),
+            Primitive::Ternary(_) => Some(3),
+            Primitive::Variadic(_) => None,
+        };
+        let args = match arity {
+            Some(arity) => {
+                let args = (0..arity).map(|_| next(current, name)?.eval(env)).collect::<Result<Vec<SExpression>, SchemeError>>()?;
+                match current.count() {
+                    0 => args,
+                    extra => return Err(SchemeError::WrongArity { expected: arity, found: arity + extra }),
+                }
             },
+            None => current.map(|sexp| sexp.eval(env)).collect::<Result<Vec<SExpression>, SchemeError>>()?,
+        };
+        match self {
+            Primitive::Unary(f) => f(&args[0]),
+            Primitive::Binary(f) => f(&args[0], &args[1]),
+            Primitive::Ternary(f) => f(&args[0], &args[1], &args[2]),
+            Primitive::Variadic(f) => f(&args),
         }
     }
 }
 
+//Bound in the outermost Frame, so a definition or a parameter can shadow them
+const PRIMITIVES: &[(&str, Primitive)] = &[
+    ("car", Primitive::Unary(SExpression::car)),
+    ("cdr", Primitive::Unary(SExpression::cdr)),
+    ("cons", Primitive::Binary(SExpression::cons)),
+    ("null?", Primitive::Unary(|sexp| Ok(sexp.is_null()))),
+    ("pair?", Primitive::Unary(|sexp| Ok(sexp.is_pair()))),
+    ("list?", Primitive::Unary(|sexp| Ok(sexp.is_list()))),
+    ("atom?", Primitive::Unary(|sexp| Ok(sexp.is_atom()))),
+    ("eq?", Primitive::Binary(SExpression::is_eq)),
+    ("equal?", Primitive::Binary(|lhs, rhs| Ok(lhs.is_equal(rhs)))),
+    ("eqlist?", Primitive::Binary(SExpression::is_eqlist)),
+    ("lat?", Primitive::Unary(|sexp| Ok(sexp.is_lat()))),
+    ("add1", Primitive::Unary(SExpression::add1)),
+    ("sub1", Primitive::Unary(SExpression::sub1)),
+    ("zero?", Primitive::Unary(SExpression::is_zero)),
+    ("number?", Primitive::Unary(|sexp| Ok(sexp.is_number()))),
+    ("boolean?", Primitive::Unary(|sexp| Ok(sexp.is_boolean()))),
+    ("symbol?", Primitive::Unary(|sexp| Ok(sexp.is_symbol()))),
+    ("string->symbol", Primitive::Unary(SExpression::string_to_symbol)),
+    ("symbol->string", Primitive::Unary(SExpression::symbol_to_string)),
+    ("string?", Primitive::Unary(|sexp| Ok(sexp.is_string()))),
+    ("string-length", Primitive::Unary(SExpression::string_length)),
+    ("string-append", Primitive::Variadic(SExpression::string_append)),
+    ("substring", Primitive::Ternary(SExpression::substring)),
+    ("string=?", Primitive::Binary(SExpression::is_string_eq)),
+    ("string->list", Primitive::Unary(SExpression::string_to_list)),
+    ("list->string", Primitive::Unary(SExpression::list_to_string)),
+    ("number->string", Primitive::Unary(SExpression::number_to_string)),
+];
+
 #[test]
+#[allow(clippy::assertions_on_constants)]
 fn test_car() {
     {
-        let tokens = to_tokens("hotdog");
+        let tokens = to_tokens("hotdog").unwrap();
         let sexp = to_sexpression(&tokens);
         match sexp {
-            Some(sexp) =>
-                assert!(match sexp.car() {
-                    None => true,
-                    _ => false,
-                }),
+            Ok(sexp) =>
+                assert_eq!(sexp.car().err(), Some(SchemeError::CarOfAtom)),
             _ => assert!(false),
         }
     }
     {
-        let tokens = to_tokens("()");
+        let tokens = to_tokens("()").unwrap();
         let sexp = to_sexpression(&tokens);
         match sexp {
-            Some(sexp) =>
-                assert!(match sexp.car() {
-                    None => true,
-                    _ => false,
-                }),
+            Ok(sexp) =>
+                assert_eq!(sexp.car().err(), Some(SchemeError::CarOfEmptyList)),
             _ => assert!(false),
         }
     }
     {
-        let tokens = to_tokens("(a b c)");
+        let tokens = to_tokens("(a b c)").unwrap();
         let sexp = to_sexpression(&tokens);
         match sexp {
-            Some(sexp) =>
+            Ok(sexp) =>
                 match sexp.car() {
-                    Some(SExpression::Atom(s)) => assert_eq!(s,"a"),
+                    Ok(SExpression::Atom(Atom::Symbol(s))) => assert_eq!(s, Symbol::new("a")),
                     _ => assert!(false),
                 },
             _ => assert!(false),
         }
     }
     {
-        let tokens = to_tokens("((a b c) x y z)");
+        let tokens = to_tokens("((a b c) x y z)").unwrap();
         let sexp = to_sexpression(&tokens);
         match sexp {
-            Some(sexp) =>
+            Ok(sexp) =>
                 match sexp.car() {
-                    Some(SExpression::List(list)) => {
-                        assert_eq!(list.len(), 3);
-                        match &list[2] {
-                            SExpression::Atom(s) => assert_eq!(s, "c"),
+                    Ok(list @ SExpression::Pair(_)) => {
+                        assert_eq!(list.iter().count(), 3);
+                        match list.iter().nth(2).unwrap() {
+                            SExpression::Atom(Atom::Symbol(s)) => assert_eq!(*s, Symbol::new("c")),
                             _ => assert!(false),
                         }
                     },
@@ -493,76 +1791,105 @@ fn test_car() {
 }
 
 #[test]
+fn test_shared_structure() {
+    let symbol = |s: &str| SExpression::Atom(Atom::Symbol(Symbol::new(s)));
+    let same_pair = |lhs: &SExpression, rhs: &SExpression| matches!((lhs, rhs), (SExpression::Pair(lhs), SExpression::Pair(rhs)) if Rc::ptr_eq(lhs, rhs));
+
+    let list = SExpression::list(vec![symbol("a"), symbol("b"), symbol("c")], SExpression::Null, None);
+    let cdr = list.cdr().unwrap();
+    let consed = symbol("x").cons(&cdr).unwrap();
+    assert!(same_pair(&cdr, &consed.cdr().unwrap()));
+    assert!(same_pair(&cdr.cdr().unwrap(), &list.cdr().unwrap().cdr().unwrap()));
+
+    //Dropping a long list doesn't recurse along it
+    let long = SExpression::list((0..1_000_000).map(|n| SExpression::Atom(Atom::Number(n))).collect(), SExpression::Null, None);
+    drop(long);
+}
+
+//`cargo test --release bench_rember -- --ignored --nocapture`; a debug build needs more stack than this for 100k levels
+#[test]
+#[ignore]
+fn bench_rember() {
+    const LENGTH: i64 = 100_000;
+    let thread = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(|| {
+        let env = Environment::new();
+        eval_scheme_to_string_in("(define rember (lambda (a lat)
+            (cond
+                ((null? lat) (quote ()))
+                ((eq? (car lat) a) (cdr lat))
+                (true (cons (car lat) (rember a (cdr lat)))))))", &env);
+        let lat = (0..LENGTH).map(|n| SExpression::Atom(Atom::Symbol(Symbol::new(&format!("a{}", n))))).collect();
+        env.define(Symbol::new("lat"), SExpression::list(lat, SExpression::Null, None));
+
+        let start = std::time::Instant::now();
+        let result = eval_scheme_to_string_in(&format!("(rember a{} lat)", LENGTH - 1), &env);
+        println!("rember of the last of {} atoms: {:?}", LENGTH, start.elapsed());
+        result
+    }).unwrap();
+    let result = thread.join().unwrap();
+    assert!(result.starts_with("(a0 a1 "));
+    assert!(result.ends_with(&format!(" a{})", LENGTH - 2)));
+}
+
+#[test]
+#[allow(clippy::assertions_on_constants)]
 fn test_eval_car() {
-    let mut env = Environment::new();
+    let env = Environment::new();
     {
-        let tokens = to_tokens("(car (a b c))");
+        let tokens = to_tokens("(car (a b c))").unwrap();
         let sexp = to_sexpression(&tokens);
         match sexp {
-            Some(sexp) => match sexp.eval(&mut env) {
-                Some(SExpression::Atom(s)) => assert_eq!(s, "a"),
+            Ok(sexp) => match sexp.eval(&env) {
+                Ok(SExpression::Atom(Atom::Symbol(s))) => assert_eq!(s, Symbol::new("a")),
                 _ => assert!(false),
             },
             _ => assert!(false),
         }
     }
     {
-        let tokens = to_tokens("(car a)");
+        let tokens = to_tokens("(car a)").unwrap();
         let sexp = to_sexpression(&tokens);
         match sexp {
-            Some(sexp) => assert!(match sexp.eval(&mut env) {
-                None => true,
-                _ => false,
-            }),
+            Ok(sexp) => assert_eq!(sexp.eval(&env).err(), Some(SchemeError::At(Span { line: 1, column: 1, start: 0, end: 7 }, Box::new(SchemeError::CarOfAtom)))),
             _ => assert!(false),
         }
     }
 }
 
-fn sexpression_to_string(sexp: &SExpression, env: &mut Environment) -> String {
-    let mut s = String::new();
-    match sexp {
-        SExpression::Atom(_) => match sexp.eval(env) {
-            Some(SExpression::Atom(s_)) => s += &s_,
-            Some(sexp) => s += &sexpression_to_string(&sexp, env),
-            _ => (),
-        },
-        SExpression::List(list) => {
-            s += "(";
-            let mut current = list.iter();
-            if let Some(sexp) = current.next() {
-                if let Some(sexp) = sexp.eval(env) {
-                    s += &sexpression_to_string(&sexp, env);
-                    while let Some(sexp) = current.next() {
-                        if let Some(sexp) = sexp.eval(env) {
-                            s += " ";
-                            s += &sexpression_to_string(&sexp, env);
-                        }
-                    }
-                }
-            }
-            s += ")";
-        }
+//Evaluate each top-level s-expression in turn, giving the value of the last one
+fn eval_program(sexps: &[SExpression], env: &Environment) -> Result<Option<SExpression>, SchemeError> {
+    let mut last = None;
+    for sexp in sexps {
+        last = Some(sexp.eval(env)?);
     }
-    s
+    Ok(last)
 }
 
+#[cfg(test)]
 fn eval_scheme_to_string(s: &str) -> String {
-    let tokens = to_tokens(s);
-    let mut env = Environment::new();
-    match to_sexpression(&tokens) {
-        Some(sexp) => match sexp.eval(&mut env) {
-            Some(sexp) => sexpression_to_string(&sexp, &mut env),
-            _ => String::from("Bad eval!"),
-        },
-        _ if s == "" => String::new(),
-        _ => String::from("Bad scheme!"),
+    eval_scheme_to_string_in(s, &Environment::new())
+}
+
+//Evaluate in an existing Environment, so definitions persist between calls
+#[cfg(test)]
+fn eval_scheme_to_string_in(s: &str, env: &Environment) -> String {
+    match eval_scheme_in(s, env) {
+        Ok(Some(sexp)) => sexp.to_string(),
+        Ok(None) => String::new(),
+        Err(err) => format!("Error: {}", err),
     }
 }
 
+fn eval_scheme_in(s: &str, env: &Environment) -> Result<Option<SExpression>, SchemeError> {
+    eval_program(&to_tokens(s).and_then(|tokens| to_sexpressions(&tokens))?, env)
+}
+
+#[cfg(test)]
 #[test_case("", ""; "eval: empty")]
 #[test_case("a", "a"; "eval: atom")]
-#[test_case("(", "Bad scheme!"; "eval: bad input")]
+#[test_case("(", "Error: unclosed list opened at 1:1"; "eval: bad input")]
+#[test_case(")", "Error: unexpected `)` at 1:1"; "eval: unexpected close bracket")]
+#[test_case("a b", "b"; "eval: program of atoms")]
 #[test_case("()", "()"; "eval: empty list")]
 #[test_case(" ( ( a  b )   c ) ", "((a b) c)"; "eval: list with whitespace")]
 #[test_case("(car (hotdogs))", "hotdogs"; "eval: car")]
@@ -570,61 +1897,965 @@ fn eval_scheme_to_string(s: &str) -> String {
 #[test_case("(car (((hotdogs))))", "((hotdogs))"; "eval: car hotdogs more nested")]
 #[test_case("(car ( ((hotdogs)) (and) (pickle) relish ) )", "((hotdogs))"; "eval: car nested list")]
 #[test_case("(car (car ( ((hotdogs)) (and) (pickle) relish ) ) )", "(hotdogs)"; "eval: nested car")]
-#[test_case("(car a)", "Bad eval!"; "eval: car of atom")]
+#[test_case("(car a)", "Error: `car` of an atom at 1:1"; "eval: car of atom")]
+#[test_case("(car ())", "Error: `car` of the empty list at 1:1"; "eval: car of empty list")]
+#[test_case("(car)", "Error: `car` is missing an argument at 1:1"; "eval: car missing argument")]
 #[test_case("(cdr (a b c) )", "(b c)"; "eval: cdr")]
 #[test_case("(cdr ((a b c) x y z) )", "(x y z)"; "eval: cdr nested list")]
 #[test_case("(cdr (hamburger) )", "()"; "eval: cdr 1-list")]
-#[test_case("(cdr a)", "Bad eval!"; "eval: cdr of atom")]
-#[test_case("(cdr ())", "Bad eval!"; "eval: cdr of empty list")]
+#[test_case("(cdr a)", "Error: `cdr` of an atom at 1:1"; "eval: cdr of atom")]
+#[test_case("(cdr ())", "Error: `cdr` of the empty list at 1:1"; "eval: cdr of empty list")]
 #[test_case("(car (cdr ((b) (x y) ((c))) ))", "(x y)"; "eval: car cdr")]
 #[test_case("(cdr (cdr ((b) (x y) ((c))) ))", "(((c)))"; "eval: cdr cdr")]
 #[test_case("(cdr (car ((b) (x y) ((c))) ))", "()"; "eval: cdr car")]
 #[test_case("(cons peanut ())", "(peanut)"; "eval: cons into empty list")]
 #[test_case("(cons () ())", "(())"; "eval: cons empty list into empty list")]
 #[test_case("(cons peanut (butter and jelly))", "(peanut butter and jelly)"; "eval: cons")]
-#[test_case("(null? spaghetti)", "false"; "eval: null? atom")]
-#[test_case("(null? ())", "true"; "eval: null? empty list")]
-#[test_case("(null? (()))", "false"; "eval: null? non-empty list")]
-#[test_case("(null? (car (())))", "true"; "eval: null? car non-empty list")]
+#[test_case("(cons peanut butter)", "(peanut . butter)"; "eval: cons onto atom")]
+#[test_case("(cons a (cons b c))", "(a b . c)"; "eval: cons dotted list")]
+#[test_case("(quote (a . b))", "(a . b)"; "eval: read dotted pair")]
+#[test_case("(quote (a b . c))", "(a b . c)"; "eval: read dotted list")]
+#[test_case("(quote (a . (b c)))", "(a b c)"; "eval: read dotted proper list")]
+#[test_case("(car (quote (a . b)))", "a"; "eval: car dotted pair")]
+#[test_case("(cdr (quote (a . b)))", "b"; "eval: cdr dotted pair")]
+#[test_case("(quote ( . a))", "Error: `.` must be between the elements and the last s-expression of a list, at 1:10"; "eval: dot first")]
+#[test_case("(quote (a . b c))", "Error: `.` must be between the elements and the last s-expression of a list, at 1:11"; "eval: dot too early")]
+#[test_case("(quote (a . ))", "Error: `.` must be between the elements and the last s-expression of a list, at 1:11"; "eval: dot last")]
+#[test_case("(a . b)", "Error: a dotted list can't be evaluated at 1:1"; "eval: dotted list")]
+#[test_case("(pair? (quote (a . b)))", "#t"; "eval: pair? dotted pair")]
+#[test_case("(pair? (a))", "#t"; "eval: pair? list")]
+#[test_case("(pair? ())", "#f"; "eval: pair? empty list")]
+#[test_case("(pair? a)", "#f"; "eval: pair? atom")]
+#[test_case("(list? (a b))", "#t"; "eval: list? list")]
+#[test_case("(list? ())", "#t"; "eval: list? empty list")]
+#[test_case("(list? (quote (a . b)))", "#f"; "eval: list? dotted pair")]
+#[test_case("(list? a)", "#f"; "eval: list? atom")]
+#[test_case("(lat? (quote (a . b)))", "#f"; "eval: lat? dotted pair")]
+#[test_case("(equal? (quote (a . b)) (cons a b))", "#t"; "eval: equal? dotted pairs")]
+#[test_case("(equal? (quote (a . b)) (a b))", "#f"; "eval: equal? dotted pair and list")]
+#[test_case("(null? spaghetti)", "#f"; "eval: null? atom")]
+#[test_case("(null? ())", "#t"; "eval: null? empty list")]
+#[test_case("(null? (()))", "#f"; "eval: null? non-empty list")]
+#[test_case("(null? (car (())))", "#t"; "eval: null? car non-empty list")]
 #[test_case("(quote ())", "()"; "eval: quote")]
-#[test_case("('())", "()"; "eval: quote apostrophe")]
-#[test_case("(null? (a b c))", "false"; "eval: null? list")]
-#[test_case("(atom? Harry)", "true"; "eval: atom? atom")]
-#[test_case("(atom? (Harry had a heap of apples))", "false"; "eval: atom? list")]
-#[test_case("(atom? ())", "false"; "eval: atom? empty list")]
-#[test_case("(atom? (car (Harry had a heap of apples)))", "true"; "eval: atom? car list")]
-#[test_case("(atom? (cdr (Harry had a heap of apples)))", "false"; "eval: atom? cdr list")]
-#[test_case("(atom? (cdr (Harry)))", "false"; "eval: atom? cdr 1-list")]
-#[test_case("(atom? (car (cdr (swing low sweet cherry oat))))", "true"; "eval: atom? car cdr list")]
-#[test_case("(atom? (car (cdr (swing (low sweet) cherry oat))))", "false"; "eval: atom? car cdr list of list")]
-#[test_case("(eq? Harry Harry)", "true"; "eval: eq? same atoms")]
-#[test_case("(eq? margarine butter)", "false"; "eval: eq? different atoms")]
-#[test_case("(eq? () (strawberry))", "false"; "eval: eq? different lists")]
-#[test_case("(eq? (strawberry tea) (strawberry tea))", "true"; "eval: eq? same lists")]
-#[test_case("(eq? 6 7)", "false"; "eval: eq? different numbers")]
-#[test_case("(eq? 7 7)", "true"; "eval: eq? same numbers")]
-#[test_case("(eq? (car (Mary had a little lamb)) Mary)", "true"; "eval: eq? car")]
-#[test_case("(eq? (cdr (soured milk)) milk)", "false"; "eval: eq? cdr list and atom")]
-#[test_case("(eq? (cdr (soured milk)) (milk))", "true"; "eval: eq? cdr list and list")]
-#[test_case("(eq? (car (beans beans we need jelly beans)) (car (cdr (beans beans we need jelly beans))) )", "true"; "eval: eq? 1st 2nd")]
-#[test_case("(lat? (Jack Sprat could eat no chicken fat) )", "true"; "eval: lat? list of atoms")]
-#[test_case("(lat? ((Jack) Sprat could eat no chicken fat) )", "false"; "eval: lat? list including list")]
-#[test_case("(lat? (Jack (Sprat could) eat no chicken fat) )", "false"; "eval: lat? another list including list")]
-#[test_case("(lat? () )", "true"; "eval: lat? empty list")]
+#[test_case("'()", "()"; "eval: quote apostrophe")]
+#[test_case("'(a b)", "(a b)"; "eval: quote apostrophe list")]
+#[test_case("(car '(a b))", "a"; "eval: quote apostrophe argument")]
+#[test_case("''a", "(quote a)"; "eval: quote apostrophe twice")]
+#[test_case("(a ')", "Error: nothing to quote after `'` at 1:4"; "eval: quote apostrophe before close bracket")]
+#[test_case("'", "Error: nothing to quote after `'` at 1:1"; "eval: quote apostrophe at end")]
+#[test_case("(null? (a b c))", "#f"; "eval: null? list")]
+#[test_case("(atom? Harry)", "#t"; "eval: atom? atom")]
+#[test_case("(atom? (Harry had a heap of apples))", "#f"; "eval: atom? list")]
+#[test_case("(atom? ())", "#f"; "eval: atom? empty list")]
+#[test_case("(atom? (car (Harry had a heap of apples)))", "#t"; "eval: atom? car list")]
+#[test_case("(atom? (cdr (Harry had a heap of apples)))", "#f"; "eval: atom? cdr list")]
+#[test_case("(atom? (cdr (Harry)))", "#f"; "eval: atom? cdr 1-list")]
+#[test_case("(atom? (car (cdr (swing low sweet cherry oat))))", "#t"; "eval: atom? car cdr list")]
+#[test_case("(atom? (car (cdr (swing (low sweet) cherry oat))))", "#f"; "eval: atom? car cdr list of list")]
+#[test_case("(eq? Harry Harry)", "#t"; "eval: eq? same atoms")]
+#[test_case("(eq? margarine butter)", "#f"; "eval: eq? different atoms")]
+#[test_case("(eq? () (strawberry))", "Error: `eq?` expects atoms that aren't numbers at 1:1"; "eval: eq? different lists")]
+#[test_case("(eq? (strawberry tea) (strawberry tea))", "Error: `eq?` expects atoms that aren't numbers at 1:1"; "eval: eq? same lists")]
+#[test_case("(eq? 6 7)", "Error: `eq?` expects atoms that aren't numbers at 1:1"; "eval: eq? different numbers")]
+#[test_case("(eq? 7 7)", "Error: `eq?` expects atoms that aren't numbers at 1:1"; "eval: eq? same numbers")]
+#[test_case("(eq? (car (Mary had a little lamb)) Mary)", "#t"; "eval: eq? car")]
+#[test_case("(eq? (cdr (soured milk)) milk)", "Error: `eq?` expects atoms that aren't numbers at 1:1"; "eval: eq? cdr list and atom")]
+#[test_case("(eq? (cdr (soured milk)) (milk))", "Error: `eq?` expects atoms that aren't numbers at 1:1"; "eval: eq? cdr list and list")]
+#[test_case("(eq? (car (beans beans we need jelly beans)) (car (cdr (beans beans we need jelly beans))) )", "#t"; "eval: eq? 1st 2nd")]
+#[test_case("(lat? (Jack Sprat could eat no chicken fat) )", "#t"; "eval: lat? list of atoms")]
+#[test_case("(lat? ((Jack) Sprat could eat no chicken fat) )", "#f"; "eval: lat? list including list")]
+#[test_case("(lat? (Jack (Sprat could) eat no chicken fat) )", "#f"; "eval: lat? another list including list")]
+#[test_case("(lat? () )", "#t"; "eval: lat? empty list")]
+#[test_case("42", "42"; "eval: number")]
+#[test_case("-7", "-7"; "eval: negative number")]
+#[test_case("#t", "#t"; "eval: true")]
+#[test_case("#false", "#f"; "eval: false")]
+#[test_case("\"hello world\"", "\"hello world\""; "eval: string")]
+#[test_case("\"say \\\"hi\\\"\"", "\"say \\\"hi\\\"\""; "eval: string with escaped quotes")]
+#[test_case("#\\a", "#\\a"; "eval: character")]
+#[test_case("(#\\( #\\space)", "(#\\( #\\space)"; "eval: characters")]
+#[test_case("\"tab\\there\\nline\\x3bb;\\|\"", "\"tab\\there\\nlineλ|\""; "eval: string escapes")]
+#[test_case("\"one \\   \n    two\"", "\"one two\""; "eval: string line continuation")]
+#[test_case("\"a\\x;\"", "Error: unknown escape in string at 1:3"; "eval: empty hex escape")]
+#[test_case("\"a\\xd800;\"", "Error: unknown escape in string at 1:3"; "eval: hex escape of a surrogate")]
+#[test_case("\"a\\ b\"", "Error: unknown escape in string at 1:3"; "eval: backslash space without line break")]
+#[test_case("(string? \"Harry\")", "#t"; "eval: string? string")]
+#[test_case("(string? Harry)", "#f"; "eval: string? symbol")]
+#[test_case("(string-length \"λx\")", "2"; "eval: string-length counts characters")]
+#[test_case("(string-length ())", "Error: `string-length` expects a string at 1:1"; "eval: string-length of a list")]
+#[test_case("(string-append \"peanut\" \" \" \"butter\")", "\"peanut butter\""; "eval: string-append")]
+#[test_case("(string-append)", "\"\""; "eval: string-append of nothing")]
+#[test_case("(string-append \"jelly\" beans)", "Error: `string-append` expects a string at 1:1"; "eval: string-append of a symbol")]
+#[test_case("(substring \"hotdog\" 3 6)", "\"dog\""; "eval: substring")]
+#[test_case("(substring \"hotdog\" 3 3)", "\"\""; "eval: empty substring")]
+#[test_case("(substring \"hotdog\" 4 3)", "Error: `substring` index out of range at 1:1"; "eval: substring backwards")]
+#[test_case("(substring \"hotdog\" 0 7)", "Error: `substring` index out of range at 1:1"; "eval: substring past the end")]
+#[test_case("(substring \"hotdog\" 0)", "Error: `substring` is missing an argument at 1:1"; "eval: substring without end")]
+#[test_case("(string=? \"lox\" (string-append \"lo\" \"x\"))", "#t"; "eval: string=? same")]
+#[test_case("(string=? \"lox\" \"Lox\")", "#f"; "eval: string=? different")]
+#[test_case("(string=? lox \"lox\")", "Error: `string=?` expects a string at 1:1"; "eval: string=? of a symbol")]
+#[test_case("(string->list \"pea\")", "(#\\p #\\e #\\a)"; "eval: string to list")]
+#[test_case("(list->string (string->list \"pea\"))", "\"pea\""; "eval: string to list and back")]
+#[test_case("(list->string (#\\a b))", "Error: `list->string` expects characters at 1:1"; "eval: list to string of a symbol")]
+#[test_case("(list->string \"pea\")", "Error: `list->string` expects lists at 1:1"; "eval: list to string of a string")]
+#[test_case("(number->string -17)", "\"-17\""; "eval: number to string")]
+#[test_case("(number->string \"17\")", "Error: `number->string` expects a number at 1:1"; "eval: number to string of a string")]
+#[test_case("\"abc", "Error: unterminated string starting at 1:1"; "eval: unterminated string")]
+#[test_case("\"a\\qb\"", "Error: unknown escape in string at 1:3"; "eval: unknown escape")]
+#[test_case("#\\bell", "Error: bad literal `#\\bell` at 1:1"; "eval: bad character")]
+#[test_case("(a #x)", "Error: bad literal `#x` at 1:4"; "eval: bad literal")]
+#[test_case("99999999999999999999", "Error: bad literal `99999999999999999999` at 1:1"; "eval: number too large")]
+#[test_case("(eq? (a b) (a c))", "Error: `eq?` expects atoms that aren't numbers at 1:1"; "eval: eq? lists that differ")]
+#[test_case("(equal? (strawberry tea) (strawberry tea))", "#t"; "eval: equal? same lists")]
+#[test_case("(equal? (a b) (a c))", "#f"; "eval: equal? lists that differ")]
+#[test_case("(equal? (a (b c)) (a (b c)))", "#t"; "eval: equal? nested lists")]
+#[test_case("(equal? (a b) (a b c))", "#f"; "eval: equal? lists of different lengths")]
+#[test_case("(equal? 7 7)", "#t"; "eval: equal? numbers")]
+#[test_case("(equal? a a)", "#t"; "eval: equal? atoms")]
+#[test_case("(equal? a (a))", "#f"; "eval: equal? atom and list")]
+#[test_case("(eqlist? (strawberry ice cream) (strawberry ice cream))", "#t"; "eval: eqlist? same")]
+#[test_case("(eqlist? (strawberry ice cream) (strawberry cream ice))", "#f"; "eval: eqlist? order matters")]
+#[test_case("(eqlist? (banana ((split))) ((banana) (split)))", "#f"; "eval: eqlist? nesting matters")]
+#[test_case("(eqlist? (beef ((sausage)) (and (soda))) (beef ((salami)) (and (soda))))", "#f"; "eval: eqlist? deep difference")]
+#[test_case("(eqlist? (beef ((sausage)) (and (soda))) (beef ((sausage)) (and (soda))))", "#t"; "eval: eqlist? deep same")]
+#[test_case("(eqlist? a (a))", "Error: `eqlist?` expects lists at 1:1"; "eval: eqlist? of an atom")]
+#[test_case("(string->symbol \"lamb\")", "lamb"; "eval: string to symbol")]
+#[test_case("(eq? (string->symbol \"lamb\") lamb)", "#t"; "eval: string to symbol is the same symbol")]
+#[test_case("(symbol->string lamb)", "\"lamb\""; "eval: symbol to string")]
+#[test_case("(string->symbol (symbol->string lamb))", "lamb"; "eval: symbol to string and back")]
+#[test_case("(string->symbol lamb)", "Error: `string->symbol` expects a string at 1:1"; "eval: string to symbol of a symbol")]
+#[test_case("(symbol->string \"lamb\")", "Error: `symbol->string` expects a symbol at 1:1"; "eval: symbol to string of a string")]
+#[test_case("(eq? true \"true\")", "#f"; "eval: eq? boolean and string")]
+#[test_case("(eq? true #t)", "#t"; "eval: eq? true and #t")]
+#[test_case("(eq? 7 \"7\")", "Error: `eq?` expects atoms that aren't numbers at 1:1"; "eval: eq? number and string")]
+#[test_case("(atom? 7)", "#t"; "eval: atom? number")]
+#[test_case("(atom? \"Harry\")", "#t"; "eval: atom? string")]
+#[test_case("(boolean? #f)", "#t"; "eval: boolean? boolean")]
+#[test_case("(boolean? (null? ()))", "#t"; "eval: boolean? null?")]
+#[test_case("(boolean? Harry)", "#f"; "eval: boolean? symbol")]
+#[test_case("(symbol? Harry)", "#t"; "eval: symbol? symbol")]
+#[test_case("(symbol? 7)", "#f"; "eval: symbol? number")]
+#[test_case("(symbol? \"Harry\")", "#f"; "eval: symbol? string")]
+#[test_case("(cond (#f a) (#t b) )", "b"; "eval: cond #f #t")]
+#[test_case("(cond (() a) )", "a"; "eval: cond only #f is false")]
+#[test_case("(add1 a)", "Error: `add1` expects a non-negative number at 1:1"; "eval: add1 of atom")]
+#[test_case("(sub1 -1)", "Error: `sub1` expects a non-negative number at 1:1"; "eval: sub1 of negative")]
+#[test_case("(add1 9223372036854775807)", "Error: number too large at 1:1"; "eval: add1 too large")]
+#[test_case("(zero? ())", "Error: `zero?` expects a non-negative number at 1:1"; "eval: zero? of list")]
+#[test_case("(number? (add1 0))", "#t"; "eval: number? add1")]
 #[test_case("(cond (true a) )", "a"; "eval: cond true")]
 #[test_case("(cond (false a) (true b) )", "b"; "eval: cond false true")]
 #[test_case("(cond (true a) (true b) )", "a"; "eval: cond first result")]
-#[test_case("(cond (false a) )", "Bad eval!"; "eval: cond no result")]
+#[test_case("(cond (false a) )", "Error: no `cond` clause matched at 1:1"; "eval: cond no result")]
+#[test_case("(cond ((car a) b) )", "Error: `car` of an atom at 1:8"; "eval: cond error in condition")]
+#[test_case("(define else true)
+             (define last (lambda (l)
+               (cond
+                 ((null? (cdr l)) (car l))
+                 (else (last (cdr l))))))
+             (last ())", "Error: `cdr` of the empty list at 4:26"; "eval: error located inside lambda body")]
+#[test_case("((a b)\n (c d)\n (e", "Error: unclosed list opened at 3:2"; "eval: unclosed list location")]
+#[test_case("(a b)\n c)", "Error: unexpected `)` at 2:3"; "eval: unexpected close bracket location")]
 #[test_case("(cond ((eq? a a) equal) (true not-equal) )", "equal"; "eval: cond apply eq? to same")]
 #[test_case("(cond ((eq? a b) equal) (true not-equal) )", "not-equal"; "eval: cond apply eq? to different")]
 #[test_case("((define else true) (cond ((eq? a b) equal) (else not-equal)) )", "(() not-equal)"; "eval: cond use else")]
 #[test_case("(define a b)", "()"; "eval: define isolated")]
-#[test_case("( (eq? a b) (eq? c c))", "(false true)"; "eval: multiple expressions")]
+#[test_case("(define (a) b)", "Error: `define` expects an atom to name at 1:1"; "eval: define non-atom")]
+#[test_case("( (eq? a b) (eq? c c))", "(#f #t)"; "eval: multiple expressions")]
 #[test_case("( (define a b) a)", "(() b)"; "eval: define substitute definition")]
+#[test_case("(define a b) a", "b"; "eval: program define substitute definition")]
+#[test_case("(define else true) (cond ((eq? a b) equal) (else not-equal))", "not-equal"; "eval: program cond use else")]
+#[test_case("(define a b) (car a) (define a (c))", "Error: `car` of an atom at 1:14"; "eval: program stops at error")]
+#[test_case("(lambda (x) x)", "#<procedure>"; "eval: lambda")]
+#[test_case("((lambda (x) x) a)", "a"; "eval: lambda apply")]
+#[test_case("((lambda (x y) (cons y x)) (b) a)", "(a b)"; "eval: lambda apply 2 args")]
+#[test_case("((lambda (x y) x) a)", "Error: expected 2 arguments but found 1 at 1:1"; "eval: lambda wrong arity")]
+#[test_case("((lambda (x (a)) x) b)", "Error: `lambda` parameters must be a list of atoms at 1:2"; "eval: lambda bad params")]
+#[test_case("(define first (lambda (l) (car l))) (first (a b c))", "a"; "eval: define lambda")]
+#[test_case("(define make (lambda (x) (lambda () x))) ((make a))", "a"; "eval: lambda captures environment")]
+#[test_case("(define x a) (define f (lambda (x) x)) ((f b) x)", "(b a)"; "eval: parameter shadows definition")]
+#[test_case("(define f (lambda (y) (define z y))) (f a) z", "z"; "eval: define inside lambda is local")]
+#[test_case("(define x a) (define x b) x", "b"; "eval: redefine")]
+#[test_case("(define a a)", "Error: `a` is defined as itself at 1:1"; "eval: define as itself")]
+#[test_case("(define a b) (define b a) b", "Error: `b` is defined as itself at 1:14"; "eval: define cycle")]
+#[test_case("(define b a) (define a b) a", "Error: `a` is defined as itself at 1:14"; "eval: define cycle the other way")]
+#[test_case("(define x 'x) x", "x"; "eval: define as its own quoted name")]
+#[test_case("(define x (quote x)) x", "x"; "eval: define as quote of its own name")]
+#[test_case("(define a b) (define a a) a", "b"; "eval: redefine bound name as itself")]
+#[test_case("(define a b) (define b c) (eq? a b)", "#f"; "eval: lookup is not re-evaluated")]
+#[test_case("(define l (quote (car (a b)))) l", "(car (a b))"; "eval: stored data is not re-executed")]
+#[test_case("(define x a) (define f (lambda () x)) (define x b) (f)", "b"; "eval: lambda sees later definitions")]
+#[test_case("(define count (lambda (n) (cond ((zero? n) done) (else (count (sub1 n)))))) (count 1000000)", "done"; "eval: tail call a million times")]
+#[test_case("(define even (lambda (n) (cond ((zero? n) #t) (else (odd (sub1 n)))))) (define odd (lambda (n) (cond ((zero? n) #f) (else (even (sub1 n)))))) (even 100001)", "#f"; "eval: mutual tail calls")]
+#[test_case("(define f (lambda (n) (cond ((zero? n) (car n)) (else (f (sub1 n)))))) (f 3)", "Error: `car` of an atom at 1:40"; "eval: error in tail call")]
+#[test_case("(define else true)
+             (define lat (lambda (l)
+               (cond
+                 ((null? l) true)
+                 ((atom? (car l)) (lat (cdr l)))
+                 (else false))))
+             ((lat (bacon and eggs))
+              (lat (bacon (and) eggs)))", "(#t #f)"; "eval: lat? in scheme")]
+#[test_case("(define else true)
+             (define member? (lambda (a lat)
+               (cond
+                 ((null? lat) false)
+                 (else (cond
+                   ((eq? (car lat) a) true)
+                   (else (member? a (cdr lat))))))))
+             ((member? meat (mashed potatoes and meat gravy))
+              (member? liver (bagels and lox)))", "(#t #f)"; "eval: member? in scheme")]
+#[test_case("(define else true)
+             (define rember (lambda (a lat)
+               (cond
+                 ((null? lat) ())
+                 ((eq? (car lat) a) (cdr lat))
+                 (else (cons (car lat) (rember a (cdr lat)))))))
+             ((rember mint (lamb chops and mint jelly))
+              (rember toast (bacon lettuce and tomato)))", "((lamb chops and jelly) (bacon lettuce and tomato))"; "eval: rember in scheme")]
+#[test_case("(define ↑ (lambda (n m) (cond ((zero? m) 1) (else (cons n (↑ n (sub1 m))))))) (↑ café 2)", "(café café . 1)"; "eval: unicode names")]
+#[test_case("(cond (#f a) (else b))", "b"; "eval: cond else")]
+#[test_case("(define else #f) (cond (#f a) (else b))", "b"; "eval: cond else is a keyword")]
+#[test_case("(if (null? ()) yes no)", "yes"; "eval: if true")]
+#[test_case("(if #f yes no)", "no"; "eval: if false")]
+#[test_case("(if () yes no)", "yes"; "eval: if only #f is false")]
+#[test_case("(if #t a (car b))", "a"; "eval: if only evaluates its branch")]
+#[test_case("(if #t yes)", "yes"; "eval: if without alternative")]
+#[test_case("(if #f yes)", "#f"; "eval: if without alternative false")]
+#[test_case("(if #t)", "Error: `if` is missing an argument at 1:1"; "eval: if without consequent")]
+#[test_case("(and)", "#t"; "eval: and of nothing")]
+#[test_case("(and 1 2)", "2"; "eval: and is the last value")]
+#[test_case("(and #f (car b))", "#f"; "eval: and short-circuits")]
+#[test_case("(or)", "#f"; "eval: or of nothing")]
+#[test_case("(or #f 3)", "3"; "eval: or is the first true value")]
+#[test_case("(or a (car b))", "a"; "eval: or short-circuits")]
+#[test_case("(not #f)", "#t"; "eval: not false")]
+#[test_case("(not ())", "#f"; "eval: not only #f is false")]
+#[test_case("(peanut butter and jelly or not if)", "(peanut butter and jelly or not if)"; "eval: keywords in the middle of a list are data")]
+#[test_case("(lat? (bacon (and) eggs))", "#f"; "eval: keyword heading a list in data is data")]
+#[test_case("(cdr (((hotdogs)) (and) (pickle) relish))", "((and) (pickle) relish)"; "eval: keyword list after a list is data")]
+#[test_case("(car (cdr (cdr (beef ((sausage)) (and (soda))))))", "(and (soda))"; "eval: keyword list is data")]
+#[test_case("(a (b (car (c d))))", "(a (b c))"; "eval: primitives in data are applied")]
+#[test_case("(define f (lambda (n) (and #t (or #f (if #t (cond ((zero? n) done) (else (f (sub1 n)))) never))))) (f 100000)", "done"; "eval: if and or tail calls")]
+#[test_case("(define member* (lambda (a l)
+               (cond
+                 ((null? l) #f)
+                 ((atom? (car l)) (or (eq? (car l) a) (member* a (cdr l))))
+                 (else (or (member* a (car l)) (member* a (cdr l)))))))
+             (member* chips ((potato) (chips ((with) fish) (chips))))", "#t"; "eval: member star in scheme")]
+#[test_case("(let ((x a) (y b)) (cons x y))", "(a . b)"; "eval: let")]
+#[test_case("(define x outer) (let ((x inner) (y x)) y)", "outer"; "eval: let values are evaluated outside")]
+#[test_case("(let ((x a)) (define z x)) z", "z"; "eval: let scope does not leak")]
+#[test_case("(let ((x a)) ((lambda () x)))", "a"; "eval: let scope is captured")]
+#[test_case("(let () a)", "a"; "eval: let without bindings")]
+#[test_case("(let (x) x)", "Error: `let` bindings must be a list of (name value) lists at 1:1"; "eval: let bad bindings")]
+#[test_case("(let ((x 1 2)) x)", "Error: `let` bindings must be a list of (name value) lists at 1:1"; "eval: let binding too long")]
+#[test_case("(let ((x 1)))", "Error: `let` is missing an argument at 1:1"; "eval: let without body")]
+#[test_case("(let* ((x 1) (y (add1 x)) (x (add1 y))) (cons x y))", "(3 . 2)"; "eval: let star")]
+#[test_case("(let* ((x a)) (define z x)) z", "z"; "eval: let star scope does not leak")]
+#[test_case("(letrec ((even? (lambda (n) (if (zero? n) #t (odd? (sub1 n))))) (odd? (lambda (n) (if (zero? n) #f (even? (sub1 n)))))) (even? 100))", "#t"; "eval: letrec")]
+#[test_case("(letrec ((x a)) (define z x)) z", "z"; "eval: letrec scope does not leak")]
+#[test_case("(let loop ((l (a b c)) (n 0)) (cond ((null? l) n) (else (loop (cdr l) (add1 n)))))", "3"; "eval: named let")]
+#[test_case("(let loop ((n 100000)) (if (zero? n) done (loop (sub1 n))))", "done"; "eval: named let tail calls")]
+#[test_case("(let loop ((n 0)) n) loop", "loop"; "eval: named let name is local")]
+#[test_case("(cdr (a (let () b)))", "((let () b))"; "eval: let in data is data")]
+#[test_case("(cdr (a (let* () b) (letrec () c)))", "((let* () b) (letrec () c))"; "eval: let star and letrec in data are data")]
+#[test_case("(begin (define x a) (cons x x))", "(a . a)"; "eval: begin")]
+#[test_case("(begin)", "Error: `begin` is missing an argument at 1:1"; "eval: empty begin")]
+#[test_case("(cdr (a (begin b c)))", "((begin b c))"; "eval: begin in data is data")]
+#[test_case("(define f (lambda (x) (define y (cons x x)) (cons y y))) (f a)", "((a . a) a . a)"; "eval: lambda body")]
+#[test_case("(define f (lambda (x) (define y x) y)) (f a) y", "y"; "eval: lambda body define is local")]
+#[test_case("(lambda (x))", "Error: `lambda` is missing an argument at 1:1"; "eval: lambda without body")]
+#[test_case("(let ((x a)) (define y b) (cons x y))", "(a . b)"; "eval: let body")]
+#[test_case("(let* ((x a)) (define y b) (cons x y))", "(a . b)"; "eval: let star body")]
+#[test_case("(let loop ((n 3) (l ())) (define m (sub1 n)) (cond ((zero? m) l) (else (loop m (cons m l)))))", "(1 2)"; "eval: named let body")]
+#[test_case("(cond ((null? ()) (define x a) (cons x x)))", "(a . a)"; "eval: cond clause body")]
+#[test_case("(cond (else a b))", "b"; "eval: cond else body")]
+#[test_case("(cond (#f a) ((car (b c))))", "b"; "eval: cond clause of just a test")]
+#[test_case("(define f (lambda (n) (define m n) (cond ((zero? m) done) (else a (begin b (f (sub1 m))))))) (f 100000)", "done"; "eval: begin and body tail calls")]
+#[test_case("(car (a b c)) ; is a #| the first |# atom #;(of the list)", "a"; "eval: comments are skipped")]
+#[test_case("car", "#<procedure car>"; "eval: primitive is a value")]
+#[test_case("(add1 1 2 3)", "Error: expected 1 arguments but found 3 at 1:1"; "eval: primitive with too many arguments")]
+#[test_case("(string-append \"a\" \"b\" \"c\")", "\"abc\""; "eval: variadic primitive takes any number")]
+#[test_case("(define first car) (first (a b))", "a"; "eval: define as a primitive")]
+#[test_case("(define f (lambda (test? x y) (test? x y))) (f eq? a a)", "#t"; "eval: primitive as an argument")]
+#[test_case("((lambda (car) (car 1)) f)", "(f 1)"; "eval: parameter shadows a primitive")]
+#[test_case("(define add1 (lambda (n) (cons n n))) (add1 1)", "(1 . 1)"; "eval: define shadows a primitive")]
 fn test_eval_scheme_to_string(s: &str, expected: &str) {
-    assert_eq!(eval_scheme_to_string(&s), expected);
+    assert_eq!(eval_scheme_to_string(s), expected);
+}
+
+#[cfg(test)]
+#[test_case("a", "Error: `a` is unbound"; "strict: unbound symbol")]
+#[test_case("'a", "a"; "strict: quoted symbol")]
+#[test_case("(car '(a b))", "a"; "strict: quoted list")]
+#[test_case("(car (a b))", "Error: `a` is unbound at 1:6"; "strict: unquoted list")]
+#[test_case("('a b)", "Error: the head of the list is not a procedure at 1:1"; "strict: head not a procedure")]
+#[test_case("(define x 'a) x", "a"; "strict: define")]
+#[test_case("(define a a)", "Error: `a` is unbound at 1:1"; "strict: define as itself")]
+#[test_case("(define x 'x) x", "x"; "strict: define as its own quoted name")]
+#[test_case("(define first (lambda (l) (car l))) (first '(a b))", "a"; "strict: lambda")]
+#[test_case("(cond ((eq? 'a 'b) 'equal) (#t 'not-equal))", "not-equal"; "strict: cond")]
+#[test_case("(cons 'a '())", "(a)"; "strict: empty list")]
+#[test_case("(cond ((null? '(a)) 'empty) (else 'full))", "full"; "strict: cond else")]
+#[test_case("(if (and 'a (or #f 'b)) (not #f) 'no)", "#t"; "strict: if and or not")]
+#[test_case("(let ((x 'a)) (let* ((y x)) (letrec ((z y)) z)))", "a"; "strict: let let star letrec")]
+#[test_case("(let ((x 'a)) x) x", "Error: `x` is unbound"; "strict: let scope does not leak")]
+#[test_case("(begin (define x 'a) (cond (else (define y 'b) (cons x y))))", "(a . b)"; "strict: begin and cond body")]
+#[test_case("(define first car) (first '(a b))", "a"; "strict: define as a primitive")]
+#[test_case("(car '(a) (undefined-fn 1 2))", "Error: expected 1 arguments but found 2 at 1:1"; "strict: primitive with too many arguments")]
+#[test_case("((lambda (car) (car '(a b))) cdr)", "(b)"; "strict: parameter shadows a primitive")]
+fn test_eval_strict(s: &str, expected: &str) {
+    assert_eq!(eval_scheme_to_string_in(s, &Environment::with_mode(Mode::Strict)), expected);
+}
+
+//The book's answers, chapter by chapter
+#[cfg(test)]
+const NUMBERS_GAMES: &str = include_str!("../chapters/04-numbers-games.scm");
+
+#[cfg(test)]
+#[test_case("(add1 67)", "68"; "numbers games: add1")]
+#[test_case("(sub1 5)", "4"; "numbers games: sub1")]
+#[test_case("(sub1 0)", "Error: `sub1` of zero at 74:1"; "numbers games: sub1 of zero")]
+#[test_case("(zero? 0)", "#t"; "numbers games: zero? 0")]
+#[test_case("(zero? 1492)", "#f"; "numbers games: zero? 1492")]
+#[test_case("(number? 76)", "#t"; "numbers games: number?")]
+#[test_case("(number? tomato)", "#f"; "numbers games: number? atom")]
+#[test_case("(+ 46 12)", "58"; "numbers games: plus")]
+#[test_case("(- 14 3)", "11"; "numbers games: minus")]
+#[test_case("(- 17 9)", "8"; "numbers games: minus again")]
+#[test_case("(addtup (3 5 2 8))", "18"; "numbers games: addtup")]
+#[test_case("(addtup (15 6 7 12 3))", "43"; "numbers games: addtup again")]
+#[test_case("(* 5 3)", "15"; "numbers games: times")]
+#[test_case("(* 13 4)", "52"; "numbers games: times again")]
+#[test_case("(tup+ (3 6 9 11 4) (8 5 2 0 7))", "(11 11 11 11 11)"; "numbers games: tup+")]
+#[test_case("(tup+ (3 7) (4 6 8 1))", "(7 13 8 1)"; "numbers games: tup+ different lengths")]
+#[test_case("(> 12 133)", "#f"; "numbers games: greater than")]
+#[test_case("(> 120 11)", "#t"; "numbers games: greater than again")]
+#[test_case("(< 4 6)", "#t"; "numbers games: less than")]
+#[test_case("(< 8 3)", "#f"; "numbers games: less than again")]
+#[test_case("(< 6 6)", "#f"; "numbers games: less than same")]
+#[test_case("(= 6 6)", "#t"; "numbers games: equal")]
+#[test_case("(↑ 1 1)", "1"; "numbers games: up arrow 1 1")]
+#[test_case("(↑ 2 3)", "8"; "numbers games: up arrow 2 3")]
+#[test_case("(↑ 5 3)", "125"; "numbers games: up arrow 5 3")]
+#[test_case("(÷ 15 4)", "3"; "numbers games: divide")]
+#[test_case("(length (hotdogs with mustard sauerkraut and pickles))", "6"; "numbers games: length")]
+#[test_case("(length (ham and cheese on rye))", "5"; "numbers games: length again")]
+#[test_case("(pick 4 (lasagna spaghetti ravioli macaroni meatball))", "macaroni"; "numbers games: pick")]
+#[test_case("(pick 0 (a))", "Error: `sub1` of zero at 36:19"; "numbers games: pick 0")]
+#[test_case("(rempick 3 (hotdogs with hot mustard))", "(hotdogs with mustard)"; "numbers games: rempick")]
+#[test_case("(no-nums (5 pears 6 prunes 9 dates))", "(pears prunes dates)"; "numbers games: no-nums")]
+#[test_case("(all-nums (5 pears 6 prunes 9 dates))", "(5 6 9)"; "numbers games: all-nums")]
+#[test_case("(eqan? 3 3)", "#t"; "numbers games: eqan? numbers")]
+#[test_case("(eqan? a a)", "#t"; "numbers games: eqan? atoms")]
+#[test_case("(eqan? 3 a)", "#f"; "numbers games: eqan? number and atom")]
+#[test_case("(eqan? 3 4)", "#f"; "numbers games: eqan? different numbers")]
+#[test_case("(eqan? a b)", "#f"; "numbers games: eqan? different atoms")]
+#[test_case("(occur 5 (5 pears 6 prunes 5 dates))", "2"; "numbers games: occur")]
+#[test_case("(one? 1)", "#t"; "numbers games: one?")]
+fn test_numbers_games(s: &str, expected: &str) {
+    assert_eq!(eval_scheme_to_string(&format!("{}\n{}", NUMBERS_GAMES, s)), expected);
+}
+
+//What the pretty-printer lays out: text that can't be broken, or a list that can be broken over lines.
+//The formatter also lays out `'` and comments as they were written.
+enum Layout {
+    Text(String),
+    List(Vec<Layout>),
+    Quoted { comments: Vec<Layout>, datum: Box<Layout> }, //Comments between the `'` and its datum go before the `'`
+    Comment { text: String, trailing: bool }, //Trailing comments follow code on the same line
+}
+
+impl Layout {
+    fn from_sexpression(sexp: &SExpression) -> Layout {
+        match sexp {
+            SExpression::Pair(_) => {
+                let mut items: Vec<Layout> = sexp.iter().map(Layout::from_sexpression).collect();
+                if !matches!(sexp.tail(), SExpression::Null) {
+                    items.push(Layout::Text(".".to_string()));
+                    items.push(Layout::from_sexpression(sexp.tail()));
+                }
+                Layout::List(items)
+            },
+            _ => Layout::Text(sexp.to_string()),
+        }
+    }
+
+    //Line comments run to the end of their line; the others can sit between elements
+    fn is_line_comment(&self) -> bool {
+        matches!(self, Layout::Comment { text, .. } if text.starts_with(';'))
+    }
+
+    //All on one line, unless there's a line comment or a comment over several lines in the way
+    fn flat(&self) -> Option<String> {
+        match self {
+            Layout::Text(text) => Some(text.clone()),
+            Layout::List(items) => Some(format!("({})", items.iter().map(Layout::flat).collect::<Option<Vec<String>>>()?.join(" "))),
+            Layout::Quoted { comments, datum } => {
+                let comments = comments.iter().map(|comment| Some(format!("{} ", comment.flat()?))).collect::<Option<String>>()?;
+                Some(format!("{}'{}", comments, datum.flat()?))
+            },
+            Layout::Comment { text, .. } if !self.is_line_comment() && !text.contains('\n') => Some(text.clone()),
+            Layout::Comment { .. } => None,
+        }
+    }
+
+    //How many elements after a keyword stay on its line, before the rest are indented as a body.
+    //A named `let` keeps its name and its bindings there.
+    fn body_start(&self, next: Option<&Layout>) -> Option<usize> {
+        match self {
+            Layout::Text(keyword) => match keyword.as_str() {
+                "let" if matches!(next, Some(Layout::Text(_))) => Some(2),
+                "define" | "lambda" | "let" | "let*" | "letrec" => Some(1),
+                "cond" | "begin" => Some(0),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    //A list that doesn't fit in what's left of the line is broken:
+    //  keywords keep their first elements on their line and indent the rest by 2,
+    //  lists of atoms fill each line,
+    //  anything else puts each element on its own line, aligned with the first argument
+    fn write(&self, out: &mut String, width: usize) {
+        let column = out[out.rfind('\n').map_or(0, |i| i + 1)..].chars().count();
+        let items = match self {
+            Layout::List(items) if !items.is_empty() && self.flat().is_none_or(|flat| column + flat.chars().count() > width) => items,
+            Layout::Quoted { comments, datum } => {
+                for comment in comments {
+                    comment.write(out, width);
+                    match comment.flat() {
+                        Some(_) => out.push(' '),
+                        None => {
+                            out.push('\n');
+                            out.push_str(&" ".repeat(column));
+                        },
+                    }
+                }
+                out.push('\'');
+                return datum.write(out, width);
+            },
+            Layout::Comment { text, .. } => return out.push_str(text),
+            _ => return out.push_str(&self.flat().unwrap_or_default()),
+        };
+        out.push('(');
+        if items.iter().all(|item| matches!(item, Layout::Text(_))) {
+            let mut line_column = column + 1;
+            for (i, item) in items.iter().enumerate() {
+                let text = item.flat().unwrap_or_default();
+                let length = text.chars().count();
+                if i > 0 && line_column + 1 + length + 1 > width {
+                    out.push('\n');
+                    out.push_str(&" ".repeat(column + 1));
+                    line_column = column + 1;
+                } else if i > 0 {
+                    out.push(' ');
+                    line_column += 1;
+                }
+                out.push_str(&text);
+                line_column += length;
+            }
+        } else {
+            let (inline, indent) = match (items[0].body_start(items.get(1)), &items[0]) {
+                (Some(inline), _) => (inline + 1, column + 2),
+                (None, Layout::Text(head)) if items.len() > 1 => (2, column + 1 + head.chars().count() + 1),
+                _ => (1, column + 1),
+            };
+            //Nothing can follow a line comment on its line
+            let mut after_comment = false;
+            for (i, item) in items.iter().enumerate() {
+                match item {
+                    Layout::Comment { trailing: true, .. } if i > 0 => out.push(' '),
+                    _ if i > 0 && (i >= inline || after_comment || matches!(item, Layout::Comment { .. })) => {
+                        out.push('\n');
+                        out.push_str(&" ".repeat(indent));
+                    },
+                    _ if i > 0 => out.push(' '),
+                    _ => (),
+                }
+                item.write(out, width);
+                after_comment = item.is_line_comment();
+            }
+            if after_comment {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+            }
+        }
+        out.push(')');
+    }
+}
+
+//Break and indent to fit within `width` columns, where possible
+fn pretty(sexp: &SExpression, width: usize) -> String {
+    let mut out = String::new();
+    Layout::from_sexpression(sexp).write(&mut out, width);
+    out
+}
+
+#[cfg(test)]
+#[test_case("(a b c)", 80, "(a b c)"; "pretty: fits")]
+#[test_case("(a b c d e f)", 8, "(a b c\n d e f)"; "pretty: fill atoms")]
+#[test_case("((a b) (c d))", 10, "((a b)\n (c d))"; "pretty: one list per line")]
+#[test_case("(f (a b) (c d))", 10, "(f (a b)\n   (c d))"; "pretty: align with first argument")]
+#[test_case("(a . (b . c))", 80, "(a b . c)"; "pretty: dotted")]
+#[test_case("(define rember (lambda (a lat) (cond ((null? lat) (quote ())) ((eq? (car lat) a) (cdr lat)) (else (cons (car lat) (rember a (cdr lat)))))))", 50,
+"(define rember
+  (lambda (a lat)
+    (cond
+      ((null? lat) (quote ()))
+      ((eq? (car lat) a) (cdr lat))
+      (else (cons (car lat) (rember a (cdr lat)))))))"; "pretty: define lambda cond")]
+#[test_case("((appetizer entree beverage) (pate boeuf vin) (beer beer beer))", 30,
+"((appetizer entree beverage)
+ (pate boeuf vin)
+ (beer beer beer))"; "pretty: table")]
+#[test_case("(((((a)))))", 4, "(((((a)))))"; "pretty: too deep to fit")]
+#[test_case("(let ((x a) (y b)) (cons x y))", 20, "(let ((x a) (y b))\n  (cons x y))"; "pretty: let")]
+#[test_case("(let loop ((lat x) (count 0)) (cond ((null? lat) count) (else (loop (cdr lat) (add1 count)))))", 50,
+"(let loop ((lat x) (count 0))
+  (cond
+    ((null? lat) count)
+    (else (loop (cdr lat) (add1 count)))))"; "pretty: named let")]
+fn test_pretty(s: &str, width: usize, expected: &str) {
+    assert_eq!(pretty(&to_sexpression(&to_tokens(s).unwrap()).unwrap(), width), expected);
+}
+
+//A token or a comment, so the formatter can see them in source order
+enum Piece<'a> {
+    Token(&'a Token),
+    Comment(&'a Comment),
+}
+
+impl Piece<'_> {
+    fn span(&self) -> Span {
+        match self {
+            Piece::Token(token) => token.span,
+            Piece::Comment(comment) => comment.span,
+        }
+    }
+
+    //Comments and strings can run over several lines
+    fn end_line(&self, source: &str) -> usize {
+        let span = self.span();
+        span.line + source[span.start..span.end].matches('\n').count()
+    }
+}
+
+//Like `to_list`, but keeping `'` and comments as they were written
+fn to_layout(source: &str, pieces: &[Piece], i: &mut usize) -> Result<Layout, SchemeError> {
+    let token = match pieces[*i] {
+        Piece::Comment(comment) => {
+            let trailing = *i > 0 && pieces[*i - 1].end_line(source) == comment.span.line;
+            *i += 1;
+            return Ok(Layout::Comment { text: comment.text.clone(), trailing });
+        },
+        Piece::Token(token) => token,
+    };
+    *i += 1;
+    match &token.kind {
+        TokenKind::OpenBracket => {
+            let mut items = Vec::new();
+            loop {
+                match pieces.get(*i) {
+                    Some(Piece::Token(Token { kind: TokenKind::CloseBracket, .. })) => {
+                        *i += 1;
+                        return Ok(Layout::List(items));
+                    },
+                    Some(_) => items.push(to_layout(source, pieces, i)?),
+                    None => return Err(SchemeError::UnclosedList(token.span)),
+                }
+            }
+        },
+        TokenKind::CloseBracket => Err(SchemeError::UnexpectedCloseBracket(token.span)),
+        TokenKind::Quote => {
+            let mut comments = Vec::new();
+            while let Some(Piece::Comment(_)) = pieces.get(*i) {
+                comments.push(to_layout(source, pieces, i)?);
+            }
+            match pieces.get(*i) {
+                Some(Piece::Token(next)) if next.kind != TokenKind::CloseBracket =>
+                    Ok(Layout::Quoted { comments, datum: Box::new(to_layout(source, pieces, i)?) }),
+                _ => Err(SchemeError::NothingToQuote(token.span)),
+            }
+        },
+        TokenKind::Atom(_) => Ok(Layout::Text(token.to_string())),
+    }
+}
+
+//The canonical layout of a whole source file, keeping its comments and the blank lines between top-level forms
+fn format_scheme(text: &str, width: usize) -> Result<String, SchemeError> {
+    let (tokens, comments) = to_tokens_and_comments(text)?;
+    to_sexpressions(&tokens)?; //Only format what would run
+    let mut pieces: Vec<Piece> = tokens.iter().map(Piece::Token).chain(comments.iter().map(Piece::Comment)).collect();
+    pieces.sort_by_key(|piece| piece.span().start);
+
+    let mut out = String::new();
+    let mut i = 0;
+    while i < pieces.len() {
+        let start = i;
+        let layout = to_layout(text, &pieces, &mut i)?;
+        if start > 0 {
+            match layout {
+                Layout::Comment { trailing: true, .. } => out.push(' '),
+                _ if pieces[start].span().line > pieces[start - 1].end_line(text) + 1 => out.push_str("\n\n"),
+                _ => out.push('\n'),
+            }
+        }
+        layout.write(&mut out, width);
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+#[test_case("", ""; "format: empty")]
+#[test_case("(define a\n   (quote b))", "(define a (quote b))\n"; "format: join lines")]
+#[test_case("'( a  b )  #true", "'(a b)\n#t\n"; "format: quote and literals")]
+#[test_case(";; Chapter 1\n(car (a b)) ; first\n\n\n(cdr (a b))", ";; Chapter 1\n(car (a b)) ; first\n\n(cdr (a b))\n"; "format: top-level comments and blank lines")]
+#[test_case("(cond ; which\n ((null? l) 0) ; none\n (else 1))", "(cond ; which\n  ((null? l) 0) ; none\n  (else 1))\n"; "format: comments in a list")]
+#[test_case("(a\n;; own line\nb)", "(a\n   ;; own line\n   b)\n"; "format: comment on its own line")]
+#[test_case("(a #| b |#\n c #;(d e))", "(a #| b |# c #;(d e))\n"; "format: block and datum comments")]
+#[test_case("#|\n  Chapter 1\n|#\n(a #| b\n |# c)", "#|\n  Chapter 1\n|#\n(a #| b\n |#\n   c)\n"; "format: block comments over lines")]
+#[test_case("(a b ; last\n)", "(a b ; last\n   )\n"; "format: comment before close bracket")]
+#[test_case("(define lat? (lambda (l) (cond ((null? l) #t) ((atom? (car l)) (lat? (cdr l))) (else #f))))",
+"(define lat?
+  (lambda (l)
+    (cond
+      ((null? l) #t)
+      ((atom? (car l)) (lat? (cdr l)))
+      (else #f))))
+"; "format: break to width")]
+#[test_case("(a '#| b |# c)", "(a #| b |# 'c)\n"; "format: block comment after quote")]
+#[test_case("(a ' ; b\n c)", "(a ; b\n   'c)\n"; "format: line comment after quote")]
+#[test_case("'; a\n(b c)", "; a\n'(b c)\n"; "format: top-level comment after quote")]
+fn test_format_scheme(s: &str, expected: &str) {
+    let formatted = format_scheme(s, 50).unwrap();
+    assert_eq!(formatted, expected);
+    assert_eq!(format_scheme(&formatted, 50).unwrap(), formatted, "formatting again changes nothing");
+}
+
+#[test]
+fn test_format_scheme_errors() {
+    assert_eq!(format_scheme("(a ; b", 80).err(), Some(SchemeError::UnclosedList(Span { line: 1, column: 1, start: 0, end: 1 })));
+    assert_eq!(format_scheme("a)", 80).err(), Some(SchemeError::UnexpectedCloseBracket(Span { line: 1, column: 2, start: 1, end: 2 })));
+}
+
+//More `(` than `)` so far, so the input continues on the next line
+fn is_unclosed(tokens: &[Token]) -> bool {
+    let mut depth = 0;
+    for token in tokens {
+        match token.kind {
+            TokenKind::OpenBracket => depth += 1,
+            TokenKind::CloseBracket => depth -= 1,
+            TokenKind::Atom(_) | TokenKind::Quote => (),
+        }
+    }
+    //A quote at the end is waiting for what it quotes
+    depth > 0 || matches!(tokens.last(), Some(Token { kind: TokenKind::Quote, .. }))
 }
 
-fn main() {
-    println!("little_schemer");
+#[cfg(test)]
+#[test_case("", false; "is_unclosed: empty")]
+#[test_case("a", false; "is_unclosed: atom")]
+#[test_case("(a", true; "is_unclosed: unclosed list")]
+#[test_case("((a)\n(b)", true; "is_unclosed: unclosed over lines")]
+#[test_case("(a))", false; "is_unclosed: too many close brackets")]
+#[test_case("(a (b) c)", false; "is_unclosed: balanced")]
+#[test_case("'", true; "is_unclosed: quote waiting")]
+fn test_is_unclosed(s: &str, expected: bool) {
+    assert_eq!(is_unclosed(&to_tokens(s).unwrap()), expected);
+}
+
+//The REPL pretty-prints its answers, and the formatter lays out files, to fit this many columns
+const WIDTH: usize = 80;
+
+//Read-eval-print loop: definitions persist between inputs, and an input continues over lines until its brackets balance
+fn repl(env: &Environment, input: impl io::BufRead, output: &mut impl io::Write) -> io::Result<()> {
+    let eval = |text: &str| match eval_scheme_in(text, env) {
+        Ok(Some(sexp)) => pretty(&sexp, WIDTH),
+        Ok(None) => String::new(),
+        Err(err) => format!("Error: {}", err),
+    };
+    let mut text = String::new();
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        text += &line?;
+        text += "\n";
+        let unclosed = match to_tokens(&text) {
+            Ok(tokens) => is_unclosed(&tokens),
+            Err(err) => matches!(err, SchemeError::UnterminatedString(_)),
+        };
+        if unclosed {
+            write!(output, "  ")?;
+        } else {
+            let result = eval(&text);
+            if !result.is_empty() {
+                writeln!(output, "{}", result)?;
+            }
+            text.clear();
+            write!(output, "> ")?;
+        }
+        output.flush()?;
+    }
+    //Report whatever was left unclosed at the end of input
+    if !text.is_empty() {
+        write!(output, "\n{}", eval(&text))?;
+    }
+    writeln!(output)
+}
+
+#[test]
+fn test_repl() {
+    let input = "(define else true)\n(define first (lambda (l)\n  (car l)))\n\n(first (a b c))\n(car a)\n(first (d e))\n";
+    let mut output = Vec::new();
+    repl(&Environment::new(), input.as_bytes(), &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(),
+        "> ()\n>   ()\n> > a\n> Error: `car` of an atom at 1:1\n> d\n> \n");
+}
+
+#[test]
+fn test_repl_pretty_prints() {
+    let input = "(quote ((appetizer entree beverage) (pate boeuf vin) (beer beer beer) (the quick brown fox)))\n";
+    let mut output = Vec::new();
+    repl(&Environment::new(), input.as_bytes(), &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(),
+        "> ((appetizer entree beverage)\n (pate boeuf vin)\n (beer beer beer)\n (the quick brown fox))\n> \n");
+}
+
+#[test]
+fn test_repl_unclosed_at_end() {
+    let mut output = Vec::new();
+    repl(&Environment::new(), "(car\n(a".as_bytes(), &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), ">     \nError: unclosed list opened at 2:1\n");
+}
+
+#[derive(Debug)]
+enum RunError {
+    Usage(String),
+    Io(io::Error),
+    Parse(SchemeError),
+    Runtime(SchemeError),
+    Unformatted(Vec<String>),
+}
+
+impl RunError {
+    //Distinct exit codes so scripts can tell what went wrong
+    fn exit_code(&self) -> u8 {
+        match self {
+            RunError::Usage(_) | RunError::Io(_) => 1,
+            RunError::Parse(_) => 2,
+            RunError::Runtime(_) => 3,
+            RunError::Unformatted(_) => 4,
+        }
+    }
+}
+
+impl From<io::Error> for RunError {
+    fn from(err: io::Error) -> RunError {
+        RunError::Io(err)
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::Usage(usage) => write!(f, "{}", usage),
+            RunError::Io(err) => write!(f, "{}", err),
+            RunError::Parse(err) | RunError::Runtime(err) => write!(f, "Error: {}", err),
+            RunError::Unformatted(paths) => write!(f, "{}", paths.iter().map(|path| format!("{} is not formatted", path)).collect::<Vec<_>>().join("\n")),
+        }
+    }
+}
+
+//Parse the whole program before running any of it, then evaluate and print each top-level s-expression in turn
+fn run_program(text: &str, env: &Environment, output: &mut impl io::Write) -> Result<(), RunError> {
+    let sexps = to_tokens(text).and_then(|tokens| to_sexpressions(&tokens)).map_err(RunError::Parse)?;
+    for sexp in sexps {
+        let sexp = sexp.eval(env).map_err(RunError::Runtime)?;
+        writeln!(output, "{}", sexp)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_run_program() {
+    {
+        let env = Environment::new();
+        let mut output = Vec::new();
+        assert!(run_program("(define a (b c))\n(car a) (cdr a)", &env, &mut output).is_ok());
+        assert_eq!(String::from_utf8(output).unwrap(), "()\nb\n(c)\n");
+
+        //Definitions persist in the Environment for the next program
+        let mut output = Vec::new();
+        assert!(run_program("a", &env, &mut output).is_ok());
+        assert_eq!(String::from_utf8(output).unwrap(), "(b c)\n");
+    }
+    {
+        let mut output = Vec::new();
+        let result = run_program("(car (a)) (car b) (car (c))", &Environment::new(), &mut output);
+        assert!(matches!(&result, Err(err @ RunError::Runtime(_)) if err.exit_code() == 3));
+        assert_eq!(String::from_utf8(output).unwrap(), "a\n");
+    }
+    {
+        let mut output = Vec::new();
+        let result = run_program("(car (a)) (car b", &Environment::new(), &mut output);
+        assert!(matches!(&result, Err(err @ RunError::Parse(_)) if err.exit_code() == 2));
+        assert!(output.is_empty());
+    }
+}
+
+const USAGE: &str = "usage: little_schemer [--strict] [file.scm | - | -e '(expr)']...
+       little_schemer fmt [--check] file.scm...";
+
+enum Source {
+    File(String),
+    Stdin,
+    Expression(String),
+}
+
+struct Options {
+    mode: Mode,
+    sources: Vec<Source>,
+}
+
+fn to_options(args: &[String]) -> Result<Options, RunError> {
+    let mut mode = Mode::Lenient;
+    let mut sources = Vec::new();
+    let mut current = args.iter();
+    while let Some(arg) = current.next() {
+        sources.push(match arg.as_str() {
+            "--strict" => {
+                mode = Mode::Strict;
+                continue;
+            },
+            "-e" => match current.next() {
+                Some(expression) => Source::Expression(expression.to_string()),
+                None => return Err(RunError::Usage(USAGE.to_string())),
+            },
+            "-" => Source::Stdin,
+            arg if arg.starts_with('-') => return Err(RunError::Usage(USAGE.to_string())),
+            arg => Source::File(arg.to_string()),
+        });
+    }
+    Ok(Options { mode, sources })
+}
+
+#[test]
+fn test_to_options() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+
+    assert!(matches!(to_options(&args(&[])), Ok(Options { mode: Mode::Lenient, sources }) if sources.is_empty()));
+    assert!(matches!(to_options(&args(&["a.scm", "-", "-e", "(car (a))", "b.scm"])).map(|options| options.sources).as_deref(),
+        Ok([Source::File(a), Source::Stdin, Source::Expression(e), Source::File(b)])
+            if a == "a.scm" && e == "(car (a))" && b == "b.scm"));
+    assert!(matches!(to_options(&args(&["--strict", "a.scm"])), Ok(Options { mode: Mode::Strict, sources }) if sources.len() == 1));
+    assert!(matches!(to_options(&args(&["-e"])), Err(RunError::Usage(_))));
+    assert!(matches!(to_options(&args(&["--verbose"])), Err(RunError::Usage(_))));
+}
+
+//Run every source in order, sharing one Environment
+fn run_sources(sources: &[Source], env: &Environment, output: &mut impl io::Write) -> Result<(), RunError> {
+    for source in sources {
+        let text = match source {
+            Source::File(path) => read_file(path)?,
+            Source::Stdin => io::read_to_string(io::stdin())?,
+            Source::Expression(expression) => expression.to_string(),
+        };
+        run_program(&text, env, output)?;
+    }
+    Ok(())
+}
+
+fn read_file(path: &str) -> Result<String, RunError> {
+    fs::read_to_string(path).map_err(|err| RunError::Io(io::Error::new(err.kind(), format!("{}: {}", path, err))))
+}
+
+//Rewrite each file in its canonical layout, or with `--check` only report the ones that would change
+fn run_fmt(args: &[String]) -> Result<(), RunError> {
+    let check = args.first().is_some_and(|arg| arg == "--check");
+    let paths = &args[usize::from(check)..];
+    if paths.is_empty() || paths.iter().any(|path| path.starts_with('-')) {
+        return Err(RunError::Usage(USAGE.to_string()));
+    }
+    let mut unformatted = Vec::new();
+    for path in paths {
+        let text = read_file(path)?;
+        let formatted = format_scheme(&text, WIDTH).map_err(RunError::Parse)?;
+        if formatted != text {
+            if check {
+                unformatted.push(path.to_string());
+            } else {
+                fs::write(path, formatted).map_err(|err| RunError::Io(io::Error::new(err.kind(), format!("{}: {}", path, err))))?;
+            }
+        }
+    }
+    match unformatted.is_empty() {
+        true => Ok(()),
+        false => Err(RunError::Unformatted(unformatted)),
+    }
+}
+
+#[test]
+fn test_run_fmt() {
+    let dir = std::env::temp_dir().join(format!("little_schemer_fmt_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let messy = dir.join("messy.scm").to_string_lossy().to_string();
+    let tidy = dir.join("tidy.scm").to_string_lossy().to_string();
+    fs::write(&messy, "(car   (a b)) ; first\n").unwrap();
+    fs::write(&tidy, "(cdr (a b))\n").unwrap();
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+
+    let result = run_fmt(&args(&["--check", &messy, &tidy]));
+    assert!(matches!(&result, Err(err @ RunError::Unformatted(paths)) if paths == std::slice::from_ref(&messy) && err.exit_code() == 4));
+    assert_eq!(fs::read_to_string(&messy).unwrap(), "(car   (a b)) ; first\n", "--check leaves files alone");
+
+    assert!(run_fmt(&args(&[&messy, &tidy])).is_ok());
+    assert_eq!(fs::read_to_string(&messy).unwrap(), "(car (a b)) ; first\n");
+    assert!(run_fmt(&args(&["--check", &messy, &tidy])).is_ok());
+
+    assert!(matches!(run_fmt(&args(&["--check"])), Err(RunError::Usage(_))));
+    fs::write(&messy, "(car (a b)").unwrap();
+    assert!(matches!(run_fmt(&args(&[&messy])), Err(err @ RunError::Parse(_)) if err.exit_code() == 2));
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(run_fmt(&args(&["--check", "chapters/04-numbers-games.scm"])).is_ok(), "the chapters are formatted");
+}
+
+fn run(args: &[String]) -> Result<(), RunError> {
+    if args.first().is_some_and(|arg| arg == "fmt") {
+        return run_fmt(&args[1..]);
+    }
+    let options = to_options(args)?;
+    let env = Environment::with_mode(options.mode);
+    match options.sources {
+        sources if sources.is_empty() => {
+            println!("little_schemer");
+            repl(&env, io::stdin().lock(), &mut io::stdout()).map_err(RunError::Io)
+        },
+        sources => run_sources(&sources, &env, &mut io::stdout()),
+    }
+}
+
+//Each level of Scheme recursion costs several Rust stack frames, so give the interpreter plenty of stack
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(&args))
+        .map_err(RunError::Io)
+        .and_then(|thread| thread.join().expect("interpreter thread panicked"));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::from(err.exit_code())
+        },
+    }
 }